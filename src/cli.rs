@@ -0,0 +1,78 @@
+use std::io::Write;
+
+use crate::config::Config;
+use crate::spool::{DeliveryStatus, Spool};
+
+const USAGE: &str = "Usage:
+  smtp-relay                                        Run the SMTP server
+  smtp-relay dead-letter list                       List dead-lettered messages
+  smtp-relay dead-letter show <id>                  Print a dead-lettered message and its failures
+  smtp-relay dead-letter replay <id> [--strategy <strategy-id>]
+                                                    Queue a dead-lettered message for delivery again";
+
+/// Run a command line subcommand instead of the server
+pub async fn run(config: &Config, args: &[String]) -> anyhow::Result<()> {
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+    match args.as_slice() {
+        ["dead-letter", rest @ ..] => dead_letter(config, rest).await,
+        ["help" | "--help" | "-h"] => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        _ => anyhow::bail!("Unknown command\n\n{}", USAGE),
+    }
+}
+
+async fn dead_letter(config: &Config, args: &[&str]) -> anyhow::Result<()> {
+    let spool_config = config
+        .spool
+        .clone()
+        .ok_or_else(|| anyhow::anyhow!("No spool configured, dead-lettered messages are only kept in spool mode"))?;
+    let spool = Spool::open(spool_config)?;
+
+    match args {
+        ["list"] => {
+            let ids = spool.list_dead().await?;
+            if ids.is_empty() {
+                println!("No dead-lettered messages");
+            }
+            for id in ids {
+                let (message, _) = spool.load_dead(&id).await?;
+                println!("{}  from={}  to={}  subject={:?}", message.id, message.from, message.to.join(","), message.subject);
+                for delivery in message.deliveries.iter().filter(|d| d.status == DeliveryStatus::Failed) {
+                    println!(
+                        "    strategy {} failed after {} attempt(s): {}",
+                        delivery.strategy,
+                        delivery.attempts,
+                        delivery.last_error.as_deref().unwrap_or("unknown error")
+                    );
+                }
+            }
+            Ok(())
+        }
+        ["show", id] => {
+            let (message, raw_data) = spool.load_dead(id).await?;
+            println!("{}", serde_json::to_string_pretty(&message)?);
+            println!();
+            std::io::stdout().write_all(&raw_data)?;
+            Ok(())
+        }
+        ["replay", id] => replay(config, &spool, id, None).await,
+        ["replay", id, "--strategy", strategy] => replay(config, &spool, id, Some(strategy)).await,
+        _ => anyhow::bail!("Unknown dead-letter command\n\n{}", USAGE),
+    }
+}
+
+async fn replay(config: &Config, spool: &Spool, id: &str, strategy: Option<&str>) -> anyhow::Result<()> {
+    if let Some(strategy) = strategy {
+        let ids: Vec<String> = config.strategies.iter().map(|s| s.id()).collect();
+        if !ids.iter().any(|id| id == strategy) {
+            anyhow::bail!("Strategy {} is not configured (available: {})", strategy, ids.join(", "));
+        }
+    }
+
+    spool.replay(id, strategy).await?;
+    println!("Message {} queued for delivery again", id);
+    Ok(())
+}
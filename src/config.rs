@@ -6,6 +6,37 @@ use std::path::Path;
 pub struct Config {
     #[serde(default = "default_smtp_port")]
     pub smtp_port: u16,
+    /// Hostname announced in the greeting and EHLO response
+    #[serde(default = "default_hostname")]
+    pub hostname: String,
+    /// Largest message accepted, in bytes (advertised via SIZE, 0 disables the limit)
+    #[serde(default = "default_max_message_size")]
+    pub max_message_size: usize,
+    /// Certificate used to offer STARTTLS on the SMTP port
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls: Option<TlsConfig>,
+    /// Additional listeners, each with its own port and certificate
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub listeners: Vec<ListenerConfig>,
+    /// SMTP AUTH users and policy
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auth: Option<AuthConfig>,
+    /// Queue accepted messages on disk and deliver them in the background
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub spool: Option<SpoolConfig>,
+    /// Which strategy outcomes count as success when delivering without a spool
+    #[serde(default)]
+    pub delivery_policy: DeliveryPolicy,
+    /// Overall deadline for delivering a message to all strategies
+    #[serde(default = "default_delivery_timeout_secs")]
+    pub delivery_timeout_secs: u64,
+    /// Rules sending matching messages to specific strategies, checked in order
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub routes: Vec<RouteConfig>,
+    /// Ids of the strategies for recipients no route matches
+    /// Every strategy is used when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_route: Option<Vec<String>>,
     pub strategies: Vec<StrategyConfig>,
 }
 
@@ -13,10 +44,32 @@ fn default_smtp_port() -> u16 {
     2525
 }
 
+fn default_delivery_timeout_secs() -> u64 {
+    60
+}
+
+fn default_hostname() -> String {
+    "smtp-relay".to_string()
+}
+
+fn default_max_message_size() -> usize {
+    25 * 1024 * 1024
+}
+
 impl Default for Config {
     fn default() -> Self {
         Self {
             smtp_port: 2525,
+            hostname: default_hostname(),
+            max_message_size: default_max_message_size(),
+            tls: None,
+            listeners: Vec::new(),
+            auth: None,
+            spool: None,
+            delivery_policy: DeliveryPolicy::default(),
+            delivery_timeout_secs: default_delivery_timeout_secs(),
+            routes: Vec::new(),
+            default_route: None,
             strategies: vec![StrategyConfig::default()],
         }
     }
@@ -25,8 +78,10 @@ impl Default for Config {
 impl Config {
     /// Load configuration from a JSON file
     pub fn from_file<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        let path = path.as_ref();
         let content = std::fs::read_to_string(path)?;
-        let config: Config = serde_json::from_str(&content)?;
+        let config: Config = serde_json::from_str(&content)
+            .map_err(|err| anyhow::anyhow!("Invalid configuration in {}: {}", path.display(), err))?;
         Ok(config)
     }
 
@@ -50,26 +105,698 @@ impl Config {
     }
 }
 
-/// Configuration for a single strategy
+/// TLS certificate and policy for a listener
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct StrategyConfig {
-    #[serde(rename = "type")]
-    pub strategy_type: String,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub api_key: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub api_url: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub extra_headers: Option<Vec<(String, String)>>,
+pub struct TlsConfig {
+    /// PEM encoded certificate chain
+    pub cert_path: String,
+    /// PEM encoded PKCS#8 private key
+    pub key_path: String,
+    /// Refuse MAIL FROM until the client has issued STARTTLS
+    #[serde(default)]
+    pub require_tls: bool,
+}
+
+/// Additional SMTP listener
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ListenerConfig {
+    pub port: u16,
+    /// Wrap connections in TLS before the greeting (SMTPS, usually port 465)
+    /// instead of offering STARTTLS
+    #[serde(default)]
+    pub implicit_tls: bool,
+    pub tls: TlsConfig,
+}
+
+/// SMTP AUTH configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuthConfig {
+    pub users: Vec<UserConfig>,
+    /// Refuse MAIL FROM until the client has authenticated
+    #[serde(default)]
+    pub require_auth: bool,
+    /// Offer AUTH on connections without TLS
+    #[serde(default)]
+    pub allow_insecure: bool,
+}
+
+/// A user allowed to authenticate
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UserConfig {
+    pub username: String,
+    /// Argon2 (`$argon2id$...`) or bcrypt (`$2b$...`) password hash
+    pub password_hash: String,
+    /// MAIL FROM addresses this user may send as (`user@example.com` or `*@example.com`)
+    /// Any sender is allowed when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allowed_senders: Option<Vec<String>>,
+}
+
+/// How strategy results map onto the reply to the SMTP client when delivering synchronously
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeliveryPolicy {
+    /// Every strategy has to accept the message
+    All,
+    /// At least one strategy has to accept the message
+    Any,
+    /// Always accept the message and only log failures
+    #[default]
+    BestEffort,
+}
+
+/// Routing rule: messages matching every condition go to the listed strategies
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RouteConfig {
+    #[serde(rename = "match")]
+    pub conditions: RouteMatch,
+    /// Ids of the target strategies
+    pub strategies: Vec<String>,
+    #[serde(default, skip_serializing_if = "RouteOverrides::is_empty")]
+    pub overrides: RouteOverrides,
+}
+
+/// Settings a route passes to its strategies in place of their own
+///
+/// Chat strategies use them to post different routes to different places;
+/// strategies without such settings ignore them.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RouteOverrides {
+    /// Slack channel to post to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub channel: Option<String>,
+    /// Name to post as on Slack and Discord
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+}
+
+impl RouteOverrides {
+    pub fn is_empty(&self) -> bool {
+        self.channel.is_none() && self.username.is_none()
+    }
+}
+
+/// Conditions of a route; unset conditions always match
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RouteMatch {
+    /// Recipient domain, `*` wildcards allowed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recipient_domain: Option<String>,
+    /// Recipient address, `*` wildcards allowed (e.g. `alerts@*`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recipient: Option<String>,
+    /// Envelope sender, `*` wildcards allowed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sender: Option<String>,
+    /// Regular expression matched against the subject
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subject: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub header: Option<HeaderMatch>,
+}
+
+/// Regular expression matched against the values of a message header
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HeaderMatch {
+    pub name: String,
+    pub pattern: String,
+}
+
+/// On-disk spool and retry schedule
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SpoolConfig {
+    /// Directory holding queued messages
+    pub dir: String,
+    /// Delay before the first retry
+    #[serde(default = "default_initial_backoff_secs")]
+    pub initial_backoff_secs: u64,
+    /// Upper bound for the delay between retries
+    #[serde(default = "default_max_backoff_secs")]
+    pub max_backoff_secs: u64,
+    /// Factor the delay grows by after each failed attempt
+    #[serde(default = "default_backoff_multiplier")]
+    pub backoff_multiplier: f64,
+    /// Give up on a message once it has been queued for this long
+    #[serde(default = "default_max_age_secs")]
+    pub max_age_secs: u64,
+    /// How often the spool is scanned for due deliveries
+    #[serde(default = "default_poll_interval_secs")]
+    pub poll_interval_secs: u64,
+}
+
+fn default_initial_backoff_secs() -> u64 {
+    30
+}
+
+fn default_max_backoff_secs() -> u64 {
+    60 * 60
+}
+
+fn default_backoff_multiplier() -> f64 {
+    2.0
+}
+
+fn default_max_age_secs() -> u64 {
+    5 * 24 * 60 * 60
+}
+
+fn default_poll_interval_secs() -> u64 {
+    10
+}
+
+/// Configuration for a single strategy, selected by its `type`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum StrategyConfig {
+    #[serde(alias = "http", alias = "generic")]
+    Webhook(WebhookConfig),
+    Resend(ResendConfig),
+    Failover(FailoverConfig),
+    Smtp(SmtpConfig),
+    Sendgrid(SendgridConfig),
+    Mailgun(MailgunConfig),
+    Postmark(PostmarkConfig),
+    Ses(SesConfig),
+    Msgraph(MsgraphConfig),
+    Gmail(GmailConfig),
+    File(FileConfig),
+    Mbox(MboxConfig),
+    Exec(ExecConfig),
+    Slack(SlackConfig),
+    Discord(DiscordConfig),
 }
 
 impl Default for StrategyConfig {
+    fn default() -> Self {
+        StrategyConfig::Webhook(WebhookConfig::default())
+    }
+}
+
+impl StrategyConfig {
+    /// Name of the strategy type
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            StrategyConfig::Webhook(_) => "webhook",
+            StrategyConfig::Resend(_) => "resend",
+            StrategyConfig::Failover(_) => "failover",
+            StrategyConfig::Smtp(_) => "smtp",
+            StrategyConfig::Sendgrid(_) => "sendgrid",
+            StrategyConfig::Mailgun(_) => "mailgun",
+            StrategyConfig::Postmark(_) => "postmark",
+            StrategyConfig::Ses(_) => "ses",
+            StrategyConfig::Msgraph(_) => "msgraph",
+            StrategyConfig::Gmail(_) => "gmail",
+            StrategyConfig::File(_) => "file",
+            StrategyConfig::Mbox(_) => "mbox",
+            StrategyConfig::Exec(_) => "exec",
+            StrategyConfig::Slack(_) => "slack",
+            StrategyConfig::Discord(_) => "discord",
+        }
+    }
+
+    /// Unique name of this strategy: the configured `id`, or its type
+    pub fn id(&self) -> String {
+        let id = match self {
+            StrategyConfig::Webhook(c) => &c.id,
+            StrategyConfig::Resend(c) => &c.id,
+            StrategyConfig::Failover(c) => &c.id,
+            StrategyConfig::Smtp(c) => &c.id,
+            StrategyConfig::Sendgrid(c) => &c.id,
+            StrategyConfig::Mailgun(c) => &c.id,
+            StrategyConfig::Postmark(c) => &c.id,
+            StrategyConfig::Ses(c) => &c.id,
+            StrategyConfig::Msgraph(c) => &c.id,
+            StrategyConfig::Gmail(c) => &c.id,
+            StrategyConfig::File(c) => &c.id,
+            StrategyConfig::Mbox(c) => &c.id,
+            StrategyConfig::Exec(c) => &c.id,
+            StrategyConfig::Slack(c) => &c.id,
+            StrategyConfig::Discord(c) => &c.id,
+        };
+        id.clone().unwrap_or_else(|| self.type_name().to_string())
+    }
+}
+
+/// Generic webhook strategy
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WebhookConfig {
+    /// Unique name used in logs, routes and the spool; defaults to the type
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(default = "default_webhook_url")]
+    pub api_url: String,
+    /// Additional request headers as `[name, value]` pairs
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub extra_headers: Vec<(String, String)>,
+}
+
+fn default_webhook_url() -> String {
+    "http://localhost:3000/email".to_string()
+}
+
+impl Default for WebhookConfig {
     fn default() -> Self {
         Self {
-            strategy_type: "webhook".to_string(),
-            api_key: None,
-            api_url: Some("http://localhost:3000/email".to_string()),
-            extra_headers: None,
+            id: None,
+            api_url: default_webhook_url(),
+            extra_headers: Vec::new(),
         }
     }
 }
+
+/// Resend API strategy
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ResendConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    pub api_key: String,
+    /// Base URL of the Resend API
+    #[serde(default = "default_resend_url")]
+    pub api_url: String,
+}
+
+fn default_resend_url() -> String {
+    "https://api.resend.com".to_string()
+}
+
+/// Ordered group of strategies where each one is only tried if the previous ones failed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FailoverConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    /// Members, tried in order
+    pub strategies: Vec<StrategyConfig>,
+    /// Consecutive failures before a member is skipped
+    #[serde(default = "default_failure_threshold")]
+    pub failure_threshold: u32,
+    /// How long a member is skipped after reaching `failure_threshold`
+    #[serde(default = "default_cooldown_secs")]
+    pub cooldown_secs: u64,
+}
+
+fn default_failure_threshold() -> u32 {
+    3
+}
+
+fn default_cooldown_secs() -> u64 {
+    60
+}
+
+/// Upstream SMTP server (smarthost) the raw message is relayed through
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SmtpConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    pub host: String,
+    #[serde(default = "default_upstream_port")]
+    pub port: u16,
+    #[serde(default)]
+    pub tls: UpstreamTls,
+    /// Authenticate with AUTH PLAIN or LOGIN when set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+    /// Name sent in EHLO
+    #[serde(default = "default_hostname")]
+    pub helo_name: String,
+    /// Skip certificate verification, for self-signed test servers only
+    #[serde(default)]
+    pub accept_invalid_certs: bool,
+    /// Timeout for connecting and for each reply from the server
+    #[serde(default = "default_upstream_timeout_secs")]
+    pub timeout_secs: u64,
+}
+
+/// How the connection to an upstream SMTP server is encrypted
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UpstreamTls {
+    /// Plaintext, only for servers on a trusted network
+    None,
+    /// Upgrade with STARTTLS and fail if the server does not offer it
+    #[default]
+    Starttls,
+    /// TLS from the first byte (SMTPS, usually port 465)
+    Implicit,
+}
+
+fn default_upstream_port() -> u16 {
+    587
+}
+
+fn default_upstream_timeout_secs() -> u64 {
+    30
+}
+
+/// SendGrid v3 Mail Send strategy
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SendgridConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    pub api_key: String,
+    /// Base URL of the SendGrid API
+    #[serde(default = "default_sendgrid_url")]
+    pub api_url: String,
+}
+
+fn default_sendgrid_url() -> String {
+    "https://api.sendgrid.com".to_string()
+}
+
+/// Mailgun strategy, uploading the raw message to `messages.mime`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MailgunConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    pub api_key: String,
+    /// Sending domain registered with Mailgun
+    pub domain: String,
+    #[serde(default)]
+    pub region: MailgunRegion,
+    /// Base URL of the Mailgun API; overrides `region`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_url: Option<String>,
+}
+
+/// Mailgun region the sending domain lives in
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MailgunRegion {
+    #[default]
+    Us,
+    Eu,
+}
+
+impl MailgunRegion {
+    pub fn api_url(&self) -> &'static str {
+        match self {
+            MailgunRegion::Us => "https://api.mailgun.net",
+            MailgunRegion::Eu => "https://api.eu.mailgun.net",
+        }
+    }
+}
+
+/// Postmark strategy
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PostmarkConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    pub server_token: String,
+    /// Message stream used unless the message has an `X-PM-Message-Stream` header;
+    /// Postmark's default transactional stream when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message_stream: Option<String>,
+    /// Base URL of the Postmark API
+    #[serde(default = "default_postmark_url")]
+    pub api_url: String,
+}
+
+fn default_postmark_url() -> String {
+    "https://api.postmarkapp.com".to_string()
+}
+
+/// Amazon SES v2 strategy; unset credentials and region are read from the standard AWS environment variables
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SesConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    /// Falls back to `AWS_REGION`, then `AWS_DEFAULT_REGION`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+    /// Falls back to `AWS_ACCESS_KEY_ID`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub access_key_id: Option<String>,
+    /// Falls back to `AWS_SECRET_ACCESS_KEY`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub secret_access_key: Option<String>,
+    /// Falls back to `AWS_SESSION_TOKEN`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session_token: Option<String>,
+    /// SES configuration set applied to every message
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub configuration_set: Option<String>,
+    /// Base URL of the SES API; defaults to `https://email.{region}.amazonaws.com`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_url: Option<String>,
+}
+
+/// Microsoft Graph `sendMail` strategy authenticating as an app registration (client credentials)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MsgraphConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    pub tenant_id: String,
+    pub client_id: String,
+    pub client_secret: String,
+    /// Mailbox (id or user principal name) that sends the mail; defaults to the envelope sender
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_id: Option<String>,
+    /// Keep a copy in the mailbox's Sent Items
+    #[serde(default)]
+    pub save_to_sent_items: bool,
+    /// Token endpoint; defaults to `https://login.microsoftonline.com/{tenant_id}/oauth2/v2.0/token`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token_url: Option<String>,
+    /// Base URL of the Graph API
+    #[serde(default = "default_msgraph_url")]
+    pub api_url: String,
+}
+
+fn default_msgraph_url() -> String {
+    "https://graph.microsoft.com/v1.0".to_string()
+}
+
+/// Gmail API strategy authenticating as a service account with domain-wide delegation
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GmailConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    /// Service account JSON key file; alternatively set `client_email` and `private_key`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub credentials_file: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_email: Option<String>,
+    /// PEM encoded RSA private key of the service account
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub private_key: Option<String>,
+    /// User to send as; defaults to the envelope sender
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subject: Option<String>,
+    /// Token endpoint; defaults to the `token_uri` of the key file or Google's endpoint
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token_url: Option<String>,
+    /// Base URL of the Gmail API
+    #[serde(default = "default_gmail_url")]
+    pub api_url: String,
+}
+
+fn default_gmail_url() -> String {
+    "https://gmail.googleapis.com".to_string()
+}
+
+/// Local file sink writing each message to a Maildir or as an `.eml` file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FileConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    pub dir: String,
+    #[serde(default)]
+    pub format: FileFormat,
+    /// Start a new subdirectory (or Maildir) every period
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rotate: Option<Rotation>,
+    /// Delete messages older than this many days
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retention_days: Option<u64>,
+}
+
+/// Layout of a file sink directory
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FileFormat {
+    /// One `.eml` file per message
+    #[default]
+    Eml,
+    /// Maildir with `tmp`, `new` and `cur`
+    Maildir,
+}
+
+/// How often a file sink starts a new subdirectory
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Rotation {
+    Hourly,
+    Daily,
+    Monthly,
+}
+
+/// Appends each message to an mbox file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MboxConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    pub path: String,
+}
+
+/// Runs a local command per message with the raw message on stdin
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExecConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    /// Program and arguments; not run through a shell
+    pub command: Vec<String>,
+    /// Kill the command if it has not finished after this long
+    #[serde(default = "default_exec_timeout_secs")]
+    pub timeout_secs: u64,
+    /// Exit codes meaning "try again later"; any other non-zero code is a permanent failure
+    #[serde(default = "default_tempfail_codes")]
+    pub tempfail_codes: Vec<i32>,
+    /// Environment variables passed on from the relay; the command gets only `PATH` and the envelope otherwise
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pass_env: Vec<String>,
+}
+
+fn default_exec_timeout_secs() -> u64 {
+    30
+}
+
+/// EX_TEMPFAIL from sysexits.h, as used by sendmail
+fn default_tempfail_codes() -> Vec<i32> {
+    vec![75]
+}
+
+/// Posts a summary of each message to a Slack incoming webhook
+///
+/// Routes can override `channel` and `username` to post different messages to different channels.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SlackConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    pub webhook_url: String,
+    /// Channel to post to instead of the webhook's default (`#alerts`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub channel: Option<String>,
+    /// Name to post as instead of the webhook's default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+}
+
+/// Posts each message to a Discord webhook as an embed with the attachments uploaded
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DiscordConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    pub webhook_url: String,
+    /// Name to post as instead of the webhook's default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    /// Total size of the attachments uploaded with one message; larger ones are left out
+    #[serde(default = "default_discord_max_upload_bytes")]
+    pub max_upload_bytes: usize,
+}
+
+/// Discord's upload limit for servers without boosts
+fn default_discord_max_upload_bytes() -> usize {
+    10 * 1024 * 1024
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn parse(value: serde_json::Value) -> Result<Config, serde_json::Error> {
+        serde_json::from_value(value)
+    }
+
+    fn error(value: serde_json::Value) -> String {
+        parse(value).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn strategies_are_parsed_by_type() {
+        let config = parse(json!({
+            "strategies": [
+                { "type": "webhook", "api_url": "https://example.com/hook" },
+                { "type": "resend", "id": "mail", "api_key": "re_123" },
+            ]
+        }))
+        .unwrap();
+
+        assert!(matches!(&config.strategies[0], StrategyConfig::Webhook(webhook) if webhook.api_url == "https://example.com/hook"));
+        assert_eq!(config.strategies[1].id(), "mail");
+        assert_eq!(config.strategies[1].type_name(), "resend");
+    }
+
+    #[test]
+    fn unknown_strategy_types_are_rejected() {
+        let message = error(json!({ "strategies": [{ "type": "carrier_pigeon" }] }));
+        assert!(message.contains("unknown variant `carrier_pigeon`"), "{}", message);
+    }
+
+    #[test]
+    fn unknown_strategy_fields_are_rejected() {
+        let message = error(json!({ "strategies": [{ "type": "resend", "api_key": "re_123", "api_ur": "x" }] }));
+        assert!(message.contains("unknown field `api_ur`"), "{}", message);
+    }
+
+    #[test]
+    fn missing_required_fields_are_rejected() {
+        let message = error(json!({ "strategies": [{ "type": "resend" }] }));
+        assert!(message.contains("missing field `api_key`"), "{}", message);
+    }
+
+    #[test]
+    fn route_overrides_are_a_named_object() {
+        let config = parse(json!({
+            "routes": [{
+                "match": { "sender": "cron@*" },
+                "strategies": ["webhook"],
+                "overrides": { "channel": "#cron" },
+            }],
+            "strategies": [{ "type": "webhook", "api_url": "https://example.com/hook" }],
+        }))
+        .unwrap();
+
+        assert_eq!(config.routes[0].overrides.channel.as_deref(), Some("#cron"));
+    }
+
+    #[test]
+    fn misspelled_route_fields_are_rejected() {
+        let route = |route: serde_json::Value| {
+            error(json!({ "routes": [route], "strategies": [{ "type": "webhook", "api_url": "https://example.com/hook" }] }))
+        };
+
+        let message = route(json!({ "match": {}, "strategy": ["webhook"] }));
+        assert!(message.contains("unknown field `strategy`"), "{}", message);
+
+        let message = route(json!({ "match": {}, "strategies": ["webhook"], "channel": "#cron" }));
+        assert!(message.contains("unknown field `channel`"), "{}", message);
+
+        let message = route(json!({ "match": {}, "strategies": ["webhook"], "overrides": { "chanel": "#cron" } }));
+        assert!(message.contains("unknown field `chanel`"), "{}", message);
+
+        let message = route(json!({ "match": { "recipient_domian": "example.com" }, "strategies": ["webhook"] }));
+        assert!(message.contains("unknown field `recipient_domian`"), "{}", message);
+    }
+}
@@ -0,0 +1,323 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::{task::JoinSet, time::Instant};
+
+use crate::config::DeliveryPolicy;
+use crate::routing::Router;
+use crate::spool::Spool;
+use crate::strategies::{failure_kind, ApiStrategy, DeliveryError, EmailData, FailureKind};
+
+/// Result of handing a message over, as reported to the SMTP client
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DispatchOutcome {
+    /// Delivered or safely queued
+    Accepted,
+    /// Not accepted, the client should try again later
+    Transient(String),
+    /// Not accepted, retrying will not help
+    Permanent(String),
+}
+
+/// Hands accepted messages to the configured strategies,
+/// either directly or through the on-disk spool
+pub struct Dispatcher {
+    strategies: Arc<Vec<ApiStrategy>>,
+    router: Router,
+    spool: Option<Arc<Spool>>,
+    policy: DeliveryPolicy,
+    timeout: Duration,
+}
+
+impl Dispatcher {
+    pub fn new(
+        strategies: Arc<Vec<ApiStrategy>>,
+        router: Router,
+        spool: Option<Arc<Spool>>,
+        policy: DeliveryPolicy,
+        timeout: Duration,
+    ) -> Self {
+        Self { strategies, router, spool, policy, timeout }
+    }
+
+    /// Deliver or queue a message
+    pub async fn dispatch(&self, email: EmailData) -> DispatchOutcome {
+        let routes = self.router.route(&email);
+        // Accepting a message no strategy will receive would silently lose it
+        if routes.is_empty() {
+            tracing::error!("No strategy for any recipient of the message from {}, rejecting it", email.from);
+            return DispatchOutcome::Permanent("No delivery route for any recipient".to_string());
+        }
+
+        if let Some(spool) = &self.spool {
+            let targets = routes
+                .into_iter()
+                .map(|(index, overrides, recipients)| (self.strategies[index].id().to_string(), overrides, recipients))
+                .collect();
+            return match spool.enqueue(&email, targets).await {
+                Ok(id) => {
+                    tracing::info!("Queued message {} from {} for delivery", id, email.from);
+                    DispatchOutcome::Accepted
+                }
+                Err(err) => {
+                    tracing::error!("Failed to queue message from {}: {}", email.from, err);
+                    DispatchOutcome::Transient("Failed to queue message".to_string())
+                }
+            };
+        }
+
+        let targets = routes
+            .into_iter()
+            .map(|(index, overrides, recipients)| (index, EmailData { to: recipients, overrides, ..email.clone() }))
+            .collect();
+        let results = fan_out(&self.strategies, targets, self.timeout).await;
+
+        let failures: Vec<(&str, FailureKind)> = results
+            .iter()
+            .filter_map(|(index, result)| {
+                let err = result.as_ref().err()?;
+                Some((self.strategies[*index].id(), failure_kind(err)))
+            })
+            .collect();
+
+        evaluate(self.policy, results.len(), &failures)
+    }
+}
+
+/// Send messages to several strategies at the same time
+///
+/// Each target is a strategy index with the email it should receive. Returns
+/// the result per target with its strategy index, in the order given. Strategies that have
+/// not finished by the deadline are cancelled and reported as transient
+/// failures, strategies that panicked as permanent ones.
+pub async fn fan_out(
+    strategies: &[ApiStrategy],
+    targets: Vec<(usize, EmailData)>,
+    timeout: Duration,
+) -> Vec<(usize, anyhow::Result<()>)> {
+    let tasks = targets
+        .into_iter()
+        .map(|(index, email)| {
+            let strategy = strategies[index].clone();
+            (index, async move { strategy.send_email(email).await })
+        })
+        .collect();
+
+    join_deliveries(tasks, timeout, |index| strategies[index].id()).await
+}
+
+/// Run delivery futures as tasks and collect their results, in the order given
+///
+/// A strategy may appear more than once, once per set of route overrides, so
+/// results are matched to deliveries by position rather than by strategy.
+async fn join_deliveries<'a, F>(
+    deliveries: Vec<(usize, F)>,
+    timeout: Duration,
+    name: impl Fn(usize) -> &'a str,
+) -> Vec<(usize, anyhow::Result<()>)>
+where
+    F: Future<Output = anyhow::Result<()>> + Send + 'static,
+{
+    let deadline = Instant::now() + timeout;
+    let mut tasks = JoinSet::new();
+    let mut positions = HashMap::new();
+
+    let targets: Vec<usize> = deliveries
+        .into_iter()
+        .enumerate()
+        .map(|(position, (index, delivery))| {
+            positions.insert(tasks.spawn(delivery).id(), position);
+            index
+        })
+        .collect();
+
+    let mut results: Vec<Option<anyhow::Result<()>>> = targets.iter().map(|_| None).collect();
+    loop {
+        match tokio::time::timeout_at(deadline, tasks.join_next_with_id()).await {
+            Ok(Some(Ok((id, result)))) => {
+                let position = positions[&id];
+                match &result {
+                    Ok(()) => tracing::info!("Email successfully forwarded via {}", name(targets[position])),
+                    Err(err) => tracing::error!("Failed to forward email via {}: {}", name(targets[position]), err),
+                }
+                results[position] = Some(result);
+            }
+            Ok(Some(Err(err))) => {
+                let position = positions[&err.id()];
+                let name = name(targets[position]);
+                tracing::error!("Delivery via {} failed unexpectedly: {}", name, err);
+                // A panic is a bug in the strategy, retrying the message will not get past it
+                let err = if err.is_panic() {
+                    DeliveryError::permanent(format!("Delivery via {} panicked", name))
+                } else {
+                    DeliveryError::transient(format!("Delivery via {} was cancelled", name))
+                };
+                results[position] = Some(Err(err.into()));
+            }
+            Ok(None) => break,
+            Err(_) => {
+                tasks.abort_all();
+                break;
+            }
+        }
+    }
+
+    targets
+        .into_iter()
+        .zip(results)
+        .map(|(index, result)| {
+            // Anything without a result is still running at the deadline
+            let result = result.unwrap_or_else(|| {
+                let name = name(index);
+                tracing::error!("Delivery via {} did not finish within {}s", name, timeout.as_secs());
+                Err(DeliveryError::transient(format!("Delivery via {} timed out after {}s", name, timeout.as_secs())).into())
+            });
+            (index, result)
+        })
+        .collect()
+}
+
+/// Apply the delivery policy to the failed strategies out of `attempted`
+fn evaluate(policy: DeliveryPolicy, attempted: usize, failures: &[(&str, FailureKind)]) -> DispatchOutcome {
+    let accepted = match policy {
+        DeliveryPolicy::All => failures.is_empty(),
+        DeliveryPolicy::Any => attempted == 0 || failures.len() < attempted,
+        DeliveryPolicy::BestEffort => true,
+    };
+    if accepted {
+        return DispatchOutcome::Accepted;
+    }
+
+    let failed: Vec<&str> = failures.iter().map(|(name, _)| *name).collect();
+    let reason = format!("Delivery failed via {}", failed.join(", "));
+
+    // Only report a permanent failure when retrying cannot change the outcome
+    if failures.iter().any(|(_, kind)| *kind == FailureKind::Transient) {
+        DispatchOutcome::Transient(reason)
+    } else {
+        DispatchOutcome::Permanent(reason)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Config, RouteConfig, RouteMatch, RouteOverrides};
+    use crate::strategies::create_strategies;
+    use wiremock::{matchers::body_partial_json, Mock, MockServer, ResponseTemplate};
+
+    #[test]
+    fn policies_map_failures_onto_outcomes() {
+        use DeliveryPolicy::{All, Any, BestEffort};
+        use FailureKind::{Permanent, Transient};
+
+        // `T` is a transient reply (451), `P` a permanent one (554)
+        let cases: &[(DeliveryPolicy, usize, &[FailureKind], &str)] = &[
+            (All, 2, &[], "accepted"),
+            (All, 2, &[Transient], "T"),
+            (All, 2, &[Permanent], "P"),
+            (All, 2, &[Permanent, Transient], "T"),
+            (All, 2, &[Permanent, Permanent], "P"),
+            (Any, 2, &[], "accepted"),
+            (Any, 2, &[Permanent], "accepted"),
+            (Any, 2, &[Transient], "accepted"),
+            (Any, 2, &[Transient, Permanent], "T"),
+            (Any, 2, &[Permanent, Permanent], "P"),
+            (Any, 0, &[], "accepted"),
+            (BestEffort, 2, &[Transient, Permanent], "accepted"),
+            (BestEffort, 2, &[Permanent, Permanent], "accepted"),
+        ];
+
+        for (policy, attempted, kinds, expected) in cases {
+            let names = ["first", "second"];
+            let failures: Vec<(&str, FailureKind)> = names.iter().copied().zip(kinds.iter().copied()).collect();
+            let outcome = evaluate(*policy, *attempted, &failures);
+            let actual = match &outcome {
+                DispatchOutcome::Accepted => "accepted",
+                DispatchOutcome::Transient(_) => "T",
+                DispatchOutcome::Permanent(_) => "P",
+            };
+            assert_eq!(actual, *expected, "{:?} with {:?} out of {}", policy, kinds, attempted);
+        }
+
+        let outcome = evaluate(All, 2, &[("first", Permanent), ("second", Permanent)]);
+        assert_eq!(outcome, DispatchOutcome::Permanent("Delivery failed via first, second".to_string()));
+    }
+
+    #[tokio::test]
+    async fn message_without_any_routed_recipient_is_rejected() {
+        let config = Config {
+            routes: vec![RouteConfig {
+                conditions: RouteMatch { recipient: Some("drop@*".to_string()), ..Default::default() },
+                strategies: Vec::new(),
+                overrides: RouteOverrides::default(),
+            }],
+            default_route: Some(Vec::new()),
+            ..Default::default()
+        };
+        let router = Router::new(&config, &[]).unwrap();
+        let dispatcher = Dispatcher::new(Arc::new(Vec::new()), router, None, DeliveryPolicy::BestEffort, Duration::from_secs(5));
+
+        let outcome = dispatcher.dispatch(EmailData::for_test(&["drop@example.com"], "\r\nhi")).await;
+        assert!(matches!(outcome, DispatchOutcome::Permanent(_)));
+    }
+
+    #[tokio::test]
+    async fn route_overrides_reach_the_strategy() {
+        let server = MockServer::start().await;
+        for channel in ["#cron", "#alerts"] {
+            Mock::given(body_partial_json(serde_json::json!({ "channel": channel })))
+                .respond_with(ResponseTemplate::new(200))
+                .expect(1)
+                .mount(&server)
+                .await;
+        }
+
+        let route = |recipient: &str, channel: &str| RouteConfig {
+            conditions: RouteMatch { recipient: Some(recipient.to_string()), ..Default::default() },
+            strategies: vec!["slack".to_string()],
+            overrides: RouteOverrides { channel: Some(channel.to_string()), username: None },
+        };
+        let config = Config {
+            routes: vec![route("cron@*", "#cron"), route("alerts@*", "#alerts")],
+            strategies: vec![serde_json::from_value(serde_json::json!({
+                "type": "slack",
+                "webhook_url": server.uri(),
+                "channel": "#general",
+            }))
+            .unwrap()],
+            ..Default::default()
+        };
+        let strategies = create_strategies(config.strategies.clone()).unwrap();
+        let router = Router::new(&config, &strategies).unwrap();
+        let dispatcher = Dispatcher::new(Arc::new(strategies), router, None, DeliveryPolicy::All, Duration::from_secs(5));
+
+        let email = EmailData::for_test(&["cron@example.com", "alerts@example.com"], "Subject: Test\r\n\r\nhi");
+        assert_eq!(dispatcher.dispatch(email).await, DispatchOutcome::Accepted);
+    }
+
+    #[tokio::test]
+    async fn panics_and_timeouts_are_reported_separately() {
+        type Delivery = std::pin::Pin<Box<dyn Future<Output = anyhow::Result<()>> + Send>>;
+        let deliveries: Vec<(usize, Delivery)> = vec![
+            (0, Box::pin(async { Ok(()) })),
+            (1, Box::pin(async { panic!("bug in strategy") })),
+            (2, Box::pin(async {
+                tokio::time::sleep(Duration::from_secs(60)).await;
+                Ok(())
+            })),
+        ];
+
+        let results = join_deliveries(deliveries, Duration::from_millis(200), |_| "test").await;
+
+        assert_eq!(results.iter().map(|(index, _)| *index).collect::<Vec<_>>(), [0, 1, 2]);
+        assert!(results[0].1.is_ok());
+        let panicked = results[1].1.as_ref().unwrap_err();
+        assert_eq!(failure_kind(panicked), FailureKind::Permanent);
+        assert!(panicked.to_string().contains("panicked"));
+        let timed_out = results[2].1.as_ref().unwrap_err();
+        assert_eq!(failure_kind(timed_out), FailureKind::Transient);
+        assert!(timed_out.to_string().contains("timed out"));
+    }
+}
@@ -1,7 +1,19 @@
 pub mod config;
+pub mod delivery;
+pub mod routing;
+pub mod spool;
 pub mod strategies;
 pub mod smtp;
 
-pub use config::{Config, StrategyConfig};
-pub use strategies::{create_strategies, ApiStrategy, EmailData};
+pub use config::{
+    AuthConfig, Config, DeliveryPolicy, DiscordConfig, ExecConfig, FailoverConfig, FileConfig,
+    FileFormat, GmailConfig, HeaderMatch, ListenerConfig, MailgunConfig, MailgunRegion, MboxConfig,
+    MsgraphConfig, PostmarkConfig, ResendConfig, Rotation, RouteConfig, RouteMatch, RouteOverrides,
+    SendgridConfig, SesConfig, SlackConfig, SmtpConfig, SpoolConfig, StrategyConfig, TlsConfig,
+    UpstreamTls, UserConfig, WebhookConfig,
+};
+pub use delivery::{DispatchOutcome, Dispatcher};
+pub use routing::Router;
+pub use spool::Spool;
+pub use strategies::{create_strategies, ApiStrategy, DeliveryError, EmailData, FailureKind};
 pub use smtp::handle_connection;
@@ -1,39 +1,99 @@
 use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::net::TcpListener;
 
+mod cli;
 mod config;
+mod delivery;
+mod routing;
+mod spool;
 mod strategies;
 mod smtp;
 
 use config::Config;
+use delivery::Dispatcher;
+use routing::Router;
+use spool::Spool;
 use strategies::create_strategies;
-use smtp::handle_connection;
+use smtp::{auth, handle_connection, tls::TlsMode};
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     tracing_subscriber::fmt::init();
 
     // Load configuration from JSON file
-    let config = Config::load()?;
+    let config = Arc::new(Config::load()?);
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if !args.is_empty() {
+        return cli::run(&config, &args).await;
+    }
     
     let smtp_port = config.smtp_port;
-    let strategies = Arc::new(create_strategies(config.strategies)?);
+    let strategies = Arc::new(create_strategies(config.strategies.clone())?);
     
-    let strategy_names: Vec<&str> = strategies.iter().map(|s| s.name()).collect();
+    let strategy_names: Vec<String> = strategies.iter().map(|s| format!("{} ({})", s.id(), s.name())).collect();
+    let tls = TlsMode::from_config(config.tls.as_ref(), false)?;
+    if let Some(auth_config) = &config.auth {
+        auth::validate(auth_config)?;
+    }
+
+    let delivery_timeout = Duration::from_secs(config.delivery_timeout_secs);
+    let spool = config.spool.clone().map(Spool::open).transpose()?.map(Arc::new);
+    if let Some(spool) = &spool {
+        tracing::info!("Spooling messages in {}", spool.config().dir);
+        tokio::spawn(spool::worker::run(Arc::clone(spool), Arc::clone(&strategies), delivery_timeout));
+    }
+    let router = Router::new(&config, &strategies)?;
+    let dispatcher = Arc::new(Dispatcher::new(
+        Arc::clone(&strategies),
+        router,
+        spool,
+        config.delivery_policy,
+        delivery_timeout,
+    ));
     
     let addr = SocketAddr::from(([0, 0, 0, 0], smtp_port));
     let listener = TcpListener::bind(addr).await?;
 
     tracing::info!("SMTP server listening on port {}", smtp_port);
     tracing::info!("Active strategies: {:?}", strategy_names);
+    if matches!(tls, TlsMode::StartTls { .. }) {
+        tracing::info!("STARTTLS enabled");
+    }
+    if let Some(auth_config) = &config.auth {
+        tracing::info!("SMTP AUTH enabled for {} user(s)", auth_config.users.len());
+    }
+
+    // Additional listeners, e.g. SMTPS on port 465
+    for listener_config in &config.listeners {
+        let listener_tls = TlsMode::from_config(Some(&listener_config.tls), listener_config.implicit_tls)?;
+        let addr = SocketAddr::from(([0, 0, 0, 0], listener_config.port));
+        let extra_listener = TcpListener::bind(addr).await?;
+
+        tracing::info!(
+            "SMTP server listening on port {} ({})",
+            listener_config.port,
+            if listener_config.implicit_tls { "implicit TLS" } else { "STARTTLS" }
+        );
+        tokio::spawn(serve(extra_listener, Arc::clone(&config), Arc::clone(&dispatcher), listener_tls));
+    }
+
+    serve(listener, config, dispatcher, tls).await;
+    Ok(())
+}
 
+/// Accept connections on a listener forever
+async fn serve(listener: TcpListener, config: Arc<Config>, dispatcher: Arc<Dispatcher>, tls: TlsMode) {
     loop {
         match listener.accept().await {
             Ok((stream, _)) => {
-                let strategies = Arc::clone(&strategies);
+                let config = Arc::clone(&config);
+                let dispatcher = Arc::clone(&dispatcher);
+                let tls = tls.clone();
                 tokio::spawn(async move {
-                    if let Err(err) = handle_connection(stream, strategies).await {
+                    if let Err(err) = handle_connection(stream, config, dispatcher, tls).await {
                         tracing::error!("Error handling connection: {:?}", err);
                     }
                 });
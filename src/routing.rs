@@ -0,0 +1,318 @@
+use regex::Regex;
+
+use crate::config::{Config, RouteConfig, RouteOverrides};
+use crate::smtp::auth::wildcard_match;
+use crate::strategies::{ApiStrategy, EmailData};
+
+/// Decides which strategies receive a message, per recipient
+///
+/// Routes are checked in order for every recipient and the first matching
+/// route wins. Recipients that match no route go to the default route. A
+/// strategy only receives the recipients routed to it, once for every set of
+/// route overrides among them.
+pub struct Router {
+    routes: Vec<Route>,
+    default_route: Vec<usize>,
+}
+
+struct Route {
+    recipient_domain: Option<String>,
+    recipient: Option<String>,
+    sender: Option<String>,
+    subject: Option<Regex>,
+    header: Option<(String, Regex)>,
+    strategies: Vec<usize>,
+    overrides: RouteOverrides,
+}
+
+impl Router {
+    /// Compile the routes in the configuration, resolving strategy ids
+    pub fn new(config: &Config, strategies: &[ApiStrategy]) -> anyhow::Result<Self> {
+        let resolve = |ids: &[String]| -> anyhow::Result<Vec<usize>> {
+            ids.iter()
+                .map(|id| {
+                    strategies
+                        .iter()
+                        .position(|s| s.id() == id)
+                        .ok_or_else(|| anyhow::anyhow!("Route refers to unknown strategy \"{}\"", id))
+                })
+                .collect()
+        };
+
+        let mut routes = Vec::new();
+        for route in &config.routes {
+            routes.push(Route::compile(route, resolve(&route.strategies)?)?);
+        }
+
+        let default_route = match &config.default_route {
+            Some(ids) => resolve(ids)?,
+            None => (0..strategies.len()).collect(),
+        };
+
+        Ok(Self { routes, default_route })
+    }
+
+    /// Strategies to deliver to, each with the overrides of the route and the recipients
+    /// routed to it, ordered by strategy
+    pub fn route(&self, email: &EmailData) -> Vec<(usize, RouteOverrides, Vec<String>)> {
+        let mut targets: Vec<(usize, RouteOverrides, Vec<String>)> = Vec::new();
+        let no_overrides = RouteOverrides::default();
+
+        for recipient in &email.to {
+            let (strategies, overrides) = match self.routes.iter().find(|route| route.matches(email, recipient)) {
+                Some(route) => (&route.strategies, &route.overrides),
+                None => (&self.default_route, &no_overrides),
+            };
+            if strategies.is_empty() {
+                tracing::warn!("No strategy for recipient {}, dropping it", recipient);
+            }
+
+            for &strategy in strategies {
+                match targets.iter_mut().find(|(index, o, _)| *index == strategy && o == overrides) {
+                    Some((_, _, recipients)) => recipients.push(recipient.clone()),
+                    None => targets.push((strategy, overrides.clone(), vec![recipient.clone()])),
+                }
+            }
+        }
+
+        targets.sort_by_key(|(index, _, _)| *index);
+        targets
+    }
+}
+
+impl Route {
+    fn compile(config: &RouteConfig, strategies: Vec<usize>) -> anyhow::Result<Self> {
+        let conditions = &config.conditions;
+
+        let subject = conditions
+            .subject
+            .as_deref()
+            .map(Regex::new)
+            .transpose()
+            .map_err(|err| anyhow::anyhow!("Invalid subject pattern in route: {}", err))?;
+        let header = conditions
+            .header
+            .as_ref()
+            .map(|header| Regex::new(&header.pattern).map(|pattern| (header.name.clone(), pattern)))
+            .transpose()
+            .map_err(|err| anyhow::anyhow!("Invalid header pattern in route: {}", err))?;
+
+        Ok(Self {
+            recipient_domain: conditions.recipient_domain.clone(),
+            recipient: conditions.recipient.clone(),
+            sender: conditions.sender.clone(),
+            subject,
+            header,
+            strategies,
+            overrides: config.overrides.clone(),
+        })
+    }
+
+    fn matches(&self, email: &EmailData, recipient: &str) -> bool {
+        if let Some(domain) = &self.recipient_domain {
+            let recipient_domain = recipient.rsplit_once('@').map(|(_, d)| d).unwrap_or("");
+            if !wildcard_match(domain, recipient_domain) {
+                return false;
+            }
+        }
+        if let Some(pattern) = &self.recipient {
+            if !wildcard_match(pattern, recipient) {
+                return false;
+            }
+        }
+        if let Some(pattern) = &self.sender {
+            if !wildcard_match(pattern, &email.from) {
+                return false;
+            }
+        }
+        if let Some(subject) = &self.subject {
+            if !subject.is_match(&email.subject) {
+                return false;
+            }
+        }
+        if let Some((name, pattern)) = &self.header {
+            if !header_values(&email.raw_text(), name).iter().any(|value| pattern.is_match(value)) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// All values of a header in the message header block, with folded lines joined
+fn header_values(raw_data: &str, name: &str) -> Vec<String> {
+    let mut values: Vec<String> = Vec::new();
+    let mut in_header = false;
+
+    for line in raw_data.lines() {
+        if line.is_empty() {
+            break;
+        }
+        if line.starts_with([' ', '\t']) {
+            if in_header {
+                if let Some(value) = values.last_mut() {
+                    value.push(' ');
+                    value.push_str(line.trim());
+                }
+            }
+            continue;
+        }
+
+        in_header = false;
+        if let Some((key, value)) = line.split_once(':') {
+            if key.trim().eq_ignore_ascii_case(name) {
+                values.push(value.trim().to_string());
+                in_header = true;
+            }
+        }
+    }
+
+    values
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::strategies::create_strategies;
+    use serde_json::json;
+    use std::sync::OnceLock;
+
+    /// Webhook strategies `a`, `b` and `c`, created once as each holds an HTTP client
+    fn strategies() -> &'static [ApiStrategy] {
+        static STRATEGIES: OnceLock<Vec<ApiStrategy>> = OnceLock::new();
+        STRATEGIES.get_or_init(|| {
+            let configs = ["a", "b", "c"].map(|id| serde_json::from_value(json!({ "type": "webhook", "id": id })).unwrap());
+            create_strategies(configs.to_vec()).unwrap()
+        })
+    }
+
+    fn router(routes: serde_json::Value, default_route: Option<&[&str]>) -> Router {
+        let config = Config {
+            routes: serde_json::from_value(routes).unwrap(),
+            default_route: default_route.map(|ids| ids.iter().map(|id| id.to_string()).collect()),
+            ..Default::default()
+        };
+        Router::new(&config, strategies()).unwrap()
+    }
+
+    fn targets(router: &Router, email: &EmailData) -> Vec<(usize, Vec<String>)> {
+        router.route(email).into_iter().map(|(index, _, recipients)| (index, recipients)).collect()
+    }
+
+    fn to(recipients: &[&str]) -> Vec<String> {
+        recipients.iter().map(|r| r.to_string()).collect()
+    }
+
+    #[test]
+    fn each_condition_selects_its_route() {
+        let email = EmailData {
+            from: "cron@host.example.com".to_string(),
+            subject: "[ALERT] Disk full".to_string(),
+            ..EmailData::for_test(&["ops@example.org"], "X-Priority: 1 (Highest)\r\nSubject: [ALERT] Disk full\r\n\r\nhi")
+        };
+        let conditions = [
+            json!({ "recipient_domain": "*.org" }),
+            json!({ "recipient": "OPS@*" }),
+            json!({ "sender": "cron@*" }),
+            json!({ "subject": "^\\[ALERT\\]" }),
+            json!({ "header": { "name": "x-priority", "pattern": "^1\\b" } }),
+        ];
+
+        for condition in conditions {
+            let matching = router(json!([{ "match": condition, "strategies": ["b"] }]), Some(&["a"]));
+            assert_eq!(targets(&matching, &email), [(1, to(&["ops@example.org"]))], "{}", condition);
+        }
+
+        let misses = [
+            json!({ "recipient_domain": "example.com" }),
+            json!({ "recipient": "dev@*" }),
+            json!({ "sender": "root@*" }),
+            json!({ "subject": "^Disk" }),
+            json!({ "header": { "name": "X-Priority", "pattern": "^5" } }),
+            json!({ "header": { "name": "X-Mailer", "pattern": "" } }),
+            // Every condition of a route has to match
+            json!({ "sender": "cron@*", "subject": "^Disk" }),
+        ];
+        for condition in misses {
+            let missing = router(json!([{ "match": condition, "strategies": ["b"] }]), Some(&["a"]));
+            assert_eq!(targets(&missing, &email), [(0, to(&["ops@example.org"]))], "{}", condition);
+        }
+    }
+
+    #[test]
+    fn first_matching_route_wins_per_recipient() {
+        let router = router(
+            json!([
+                { "match": { "recipient": "alerts@*" }, "strategies": ["c"] },
+                { "match": { "recipient_domain": "example.com" }, "strategies": ["b", "c"] },
+            ]),
+            None,
+        );
+        let email = EmailData::for_test(&["alerts@example.com", "dev@example.com", "x@example.org"], "Subject: Test\r\n\r\nhi");
+
+        assert_eq!(
+            targets(&router, &email),
+            [
+                (0, to(&["x@example.org"])),
+                (1, to(&["dev@example.com", "x@example.org"])),
+                (2, to(&["alerts@example.com", "dev@example.com", "x@example.org"])),
+            ]
+        );
+    }
+
+    #[test]
+    fn unrouted_recipients_use_the_default_route() {
+        let router = router(json!([{ "match": { "recipient": "drop@*" }, "strategies": [] }]), Some(&["b"]));
+        let email = EmailData::for_test(&["drop@example.com", "keep@example.com"], "Subject: Test\r\n\r\nhi");
+        assert_eq!(targets(&router, &email), [(1, to(&["keep@example.com"]))]);
+
+        // Nothing left to deliver to, so the dispatcher rejects the message
+        let email = EmailData::for_test(&["drop@example.com"], "Subject: Test\r\n\r\nhi");
+        assert!(router.route(&email).is_empty());
+    }
+
+    #[test]
+    fn recipients_with_different_overrides_are_delivered_separately() {
+        let router = router(
+            json!([
+                { "match": { "recipient": "cron@*" }, "strategies": ["a"], "overrides": { "channel": "#cron" } },
+                { "match": { "recipient": "backup@*" }, "strategies": ["a"], "overrides": { "channel": "#cron" } },
+            ]),
+            Some(&["a"]),
+        );
+        let email = EmailData::for_test(&["cron@example.com", "dev@example.com", "backup@example.com"], "Subject: Test\r\n\r\nhi");
+        let cron = RouteOverrides { channel: Some("#cron".to_string()), username: None };
+
+        assert_eq!(
+            router.route(&email),
+            [
+                (0, cron, to(&["cron@example.com", "backup@example.com"])),
+                (0, RouteOverrides::default(), to(&["dev@example.com"])),
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_strategies_and_bad_patterns_are_rejected() {
+        let config = Config {
+            routes: serde_json::from_value(json!([{ "match": {}, "strategies": ["missing"] }])).unwrap(),
+            ..Default::default()
+        };
+        let error = Router::new(&config, strategies()).err().unwrap();
+        assert!(error.to_string().contains("unknown strategy \"missing\""));
+
+        let config = Config {
+            routes: serde_json::from_value(json!([{ "match": { "subject": "(" }, "strategies": ["a"] }])).unwrap(),
+            ..Default::default()
+        };
+        assert!(Router::new(&config, strategies()).is_err());
+    }
+
+    #[test]
+    fn header_values_joins_folded_lines() {
+        let raw = "X-Tag: one\r\nx-tag: two,\r\n  three\r\n\tfour\r\nSubject: Test\r\n continued\r\n\r\nX-Tag: body";
+        assert_eq!(header_values(raw, "X-Tag"), ["one", "two, three four"]);
+        assert_eq!(header_values(raw, "Subject"), ["Test continued"]);
+        assert!(header_values(raw, "X-Other").is_empty());
+    }
+}
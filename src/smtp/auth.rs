@@ -0,0 +1,236 @@
+use argon2::{password_hash::PasswordHash, Argon2, PasswordVerifier};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+
+use crate::config::{AuthConfig, UserConfig};
+
+/// SASL mechanisms offered in the EHLO response
+pub const MECHANISMS: &str = "PLAIN LOGIN";
+
+/// Progress of a multi-step AUTH exchange
+#[derive(Debug)]
+pub enum AuthState {
+    /// AUTH PLAIN sent without an initial response
+    PlainResponse,
+    /// AUTH LOGIN waiting for the username
+    LoginUsername,
+    /// AUTH LOGIN waiting for the password
+    LoginPassword { username: String },
+}
+
+/// Check that every configured password hash can be parsed
+pub fn validate(config: &AuthConfig) -> anyhow::Result<()> {
+    for user in &config.users {
+        if !is_argon2(&user.password_hash) && !is_bcrypt(&user.password_hash) {
+            anyhow::bail!("Unsupported password hash for user {}, expected argon2 or bcrypt", user.username);
+        }
+        if is_argon2(&user.password_hash) {
+            PasswordHash::new(&user.password_hash)
+                .map_err(|err| anyhow::anyhow!("Invalid argon2 hash for user {}: {}", user.username, err))?;
+        }
+    }
+    Ok(())
+}
+
+/// Look up a user and verify their password
+/// Hashing is CPU bound, so it runs on the blocking thread pool
+pub async fn authenticate(config: &AuthConfig, username: &str, password: &str) -> bool {
+    let Some(user) = config.users.iter().find(|u| u.username == username) else {
+        return false;
+    };
+
+    let user: UserConfig = user.clone();
+    let password = password.to_string();
+    tokio::task::spawn_blocking(move || verify_password(&user.password_hash, &password))
+        .await
+        .unwrap_or(false)
+}
+
+/// Whether an authenticated user may use the given MAIL FROM address
+pub fn sender_allowed(config: &AuthConfig, username: &str, sender: &str) -> bool {
+    let Some(user) = config.users.iter().find(|u| u.username == username) else {
+        return false;
+    };
+
+    match &user.allowed_senders {
+        None => true,
+        Some(patterns) => patterns.iter().any(|pattern| wildcard_match(pattern, sender)),
+    }
+}
+
+/// Case-insensitive match where `*` stands for any run of characters
+pub fn wildcard_match(pattern: &str, value: &str) -> bool {
+    let pattern = pattern.to_lowercase();
+    let value = value.to_lowercase();
+
+    let mut segments = pattern.split('*');
+    let first = segments.next().unwrap_or("");
+    let Some(mut rest) = value.strip_prefix(first) else {
+        return false;
+    };
+
+    let segments: Vec<&str> = segments.collect();
+    let Some((last, middle)) = segments.split_last() else {
+        // No wildcard at all, so the whole value has to match
+        return rest.is_empty();
+    };
+
+    for segment in middle {
+        match rest.find(segment) {
+            Some(pos) => rest = &rest[pos + segment.len()..],
+            None => return false,
+        }
+    }
+
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+fn verify_password(hash: &str, password: &str) -> bool {
+    if is_argon2(hash) {
+        match PasswordHash::new(hash) {
+            Ok(parsed) => Argon2::default().verify_password(password.as_bytes(), &parsed).is_ok(),
+            Err(_) => false,
+        }
+    } else if is_bcrypt(hash) {
+        bcrypt::verify(password, hash).unwrap_or(false)
+    } else {
+        false
+    }
+}
+
+fn is_argon2(hash: &str) -> bool {
+    hash.starts_with("$argon2")
+}
+
+fn is_bcrypt(hash: &str) -> bool {
+    ["$2a$", "$2b$", "$2x$", "$2y$"].iter().any(|prefix| hash.starts_with(prefix))
+}
+
+/// Decode a base64 SASL response into a UTF-8 string
+pub fn decode_response(response: &str) -> Option<String> {
+    let decoded = BASE64.decode(response.trim()).ok()?;
+    String::from_utf8(decoded).ok()
+}
+
+/// Split a PLAIN response (`authzid NUL authcid NUL passwd`) into username and password
+pub fn parse_plain(response: &str) -> Option<(String, String)> {
+    let decoded = decode_response(response)?;
+    let mut fields = decoded.split('\0');
+    let authzid = fields.next()?;
+    let username = fields.next()?;
+    let password = fields.next()?;
+
+    if fields.next().is_some() || username.is_empty() {
+        return None;
+    }
+    // Acting on behalf of another identity is not supported
+    if !authzid.is_empty() && authzid != username {
+        return None;
+    }
+
+    Some((username.to_string(), password.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use argon2::password_hash::{PasswordHasher, SaltString};
+    use argon2::{Algorithm, Params, Version};
+
+    /// Argon2id hash with minimal cost parameters, so tests stay fast
+    fn argon2_hash(password: &str) -> String {
+        let params = Params::new(8, 1, 1, None).unwrap();
+        let salt = SaltString::encode_b64(b"smtp-relay-salt").unwrap();
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password(password.as_bytes(), &salt)
+            .unwrap()
+            .to_string()
+    }
+
+    fn user(username: &str, password_hash: String) -> UserConfig {
+        UserConfig { username: username.to_string(), password_hash, allowed_senders: None }
+    }
+
+    fn config(users: Vec<UserConfig>) -> AuthConfig {
+        AuthConfig { users, require_auth: false, allow_insecure: false }
+    }
+
+    #[test]
+    fn parse_plain_reads_username_and_password() {
+        let response = BASE64.encode("\0alice\0secret");
+        assert_eq!(parse_plain(&response), Some(("alice".to_string(), "secret".to_string())));
+
+        // An authorization identity equal to the username is the same login
+        let response = BASE64.encode("alice\0alice\0secret");
+        assert_eq!(parse_plain(&response), Some(("alice".to_string(), "secret".to_string())));
+    }
+
+    #[test]
+    fn parse_plain_rejects_malformed_responses() {
+        // Acting as somebody else
+        assert_eq!(parse_plain(&BASE64.encode("bob\0alice\0secret")), None);
+        // Missing NULs
+        assert_eq!(parse_plain(&BASE64.encode("alice secret")), None);
+        assert_eq!(parse_plain(&BASE64.encode("\0alice")), None);
+        // Too many fields
+        assert_eq!(parse_plain(&BASE64.encode("\0alice\0secret\0extra")), None);
+        assert_eq!(parse_plain(&BASE64.encode("\0\0secret")), None);
+        assert_eq!(parse_plain("not base64!"), None);
+    }
+
+    #[test]
+    fn decode_response_requires_base64_utf8() {
+        assert_eq!(decode_response(" YWxpY2U= "), Some("alice".to_string()));
+        assert_eq!(decode_response("YWxpY2U"), None);
+        assert_eq!(decode_response(&BASE64.encode([0xff, 0xfe])), None);
+    }
+
+    #[test]
+    fn validate_accepts_argon2_and_bcrypt_hashes() {
+        let users = vec![user("alice", argon2_hash("secret")), user("bob", bcrypt::hash("secret", 4).unwrap())];
+        assert!(validate(&config(users)).is_ok());
+
+        let error = validate(&config(vec![user("carol", "plaintext".to_string())])).unwrap_err();
+        assert!(error.to_string().contains("Unsupported password hash for user carol"));
+
+        let error = validate(&config(vec![user("dave", "$argon2id$v=19$m=8,t=1,p=1$not base64!".to_string())])).unwrap_err();
+        assert!(error.to_string().contains("Invalid argon2 hash for user dave"));
+    }
+
+    #[tokio::test]
+    async fn authenticate_checks_the_password_hash() {
+        let config = config(vec![user("alice", argon2_hash("secret")), user("bob", bcrypt::hash("hunter2", 4).unwrap())]);
+
+        assert!(authenticate(&config, "alice", "secret").await);
+        assert!(authenticate(&config, "bob", "hunter2").await);
+        assert!(!authenticate(&config, "alice", "hunter2").await);
+        assert!(!authenticate(&config, "bob", "secret").await);
+        assert!(!authenticate(&config, "mallory", "secret").await);
+    }
+
+    #[test]
+    fn wildcard_match_is_case_insensitive() {
+        assert!(wildcard_match("alerts@example.com", "Alerts@Example.COM"));
+        assert!(!wildcard_match("alerts@example.com", "alerts@example.com.evil"));
+        assert!(wildcard_match("*@example.com", "anyone@example.com"));
+        assert!(!wildcard_match("*@example.com", "anyone@example.org"));
+        assert!(!wildcard_match("*@example.com", "anyone@notexample.com.au"));
+        assert!(wildcard_match("alerts@*", "alerts@example.com"));
+        assert!(wildcard_match("*+*@example.com", "a+tag@example.com"));
+        assert!(!wildcard_match("a*a", "a"));
+    }
+
+    #[test]
+    fn sender_allowed_follows_the_user_patterns() {
+        let mut restricted = user("alice", String::new());
+        restricted.allowed_senders = Some(vec!["alice@example.com".to_string(), "*@alerts.example.com".to_string()]);
+        let config = config(vec![restricted, user("bob", String::new())]);
+
+        assert!(sender_allowed(&config, "alice", "alice@example.com"));
+        assert!(sender_allowed(&config, "alice", "ALICE@Example.com"));
+        assert!(sender_allowed(&config, "alice", "cron@alerts.example.com"));
+        assert!(!sender_allowed(&config, "alice", "bob@example.com"));
+        // Users without `allowed_senders` may send as anyone
+        assert!(sender_allowed(&config, "bob", "ceo@example.com"));
+        assert!(!sender_allowed(&config, "mallory", "mallory@example.com"));
+    }
+}
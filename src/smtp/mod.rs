@@ -1,73 +1,284 @@
+pub mod auth;
 pub mod session;
+pub mod tls;
 
 use std::sync::Arc;
-use tokio::{io::{AsyncBufReadExt, AsyncWriteExt, BufReader, BufWriter}, net::TcpStream};
+use tokio::{io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufStream}, net::TcpStream};
 
-use crate::strategies::ApiStrategy;
+use crate::config::Config;
+use crate::delivery::Dispatcher;
 use session::SmtpSession;
+use tls::TlsMode;
 
-pub async fn handle_connection(mut stream: TcpStream, strategies: Arc<Vec<ApiStrategy>>) -> anyhow::Result<()> {
+/// Any byte stream an SMTP session can run over (plain TCP or TLS)
+pub(crate) trait SmtpStream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> SmtpStream for T {}
+
+pub async fn handle_connection(
+    stream: TcpStream,
+    config: Arc<Config>,
+    dispatcher: Arc<Dispatcher>,
+    tls: TlsMode,
+) -> anyhow::Result<()> {
     let addr = stream.peer_addr()?;
     tracing::info!("New connection from {}", addr);
 
-    let (reader, writer) = stream.split();
-    let mut reader = BufReader::new(reader);
-    let mut writer = BufWriter::new(writer);
+    let (tls_available, require_tls) = match &tls {
+        TlsMode::StartTls { required, .. } => (true, *required),
+        TlsMode::None | TlsMode::Implicit(_) => (false, false),
+    };
+    let mut session = SmtpSession::new(Arc::clone(&config), dispatcher, tls_available, require_tls);
+
+    let mut stream: BufStream<Box<dyn SmtpStream>> = match &tls {
+        TlsMode::Implicit(acceptor) => {
+            let tls_stream = acceptor.accept(stream).await?;
+            tracing::info!("TLS established with {}", addr);
+            session.tls_established();
+            BufStream::new(Box::new(tls_stream))
+        }
+        _ => BufStream::new(Box::new(stream)),
+    };
 
-    writer.write_all(b"220 SMTP Server Ready\r\n").await?;
-    writer.flush().await?;
+    stream.write_all(format!("220 {} ESMTP SMTP Server Ready\r\n", config.hostname).as_bytes()).await?;
+    stream.flush().await?;
 
-    let mut session = SmtpSession::new(strategies);
     let mut line = String::new();
 
     loop {
         line.clear();
         
         if session.expecting_data {
-            let mut data_lines: Vec<String> = Vec::new();
+            let max_size = session.max_message_size();
+            let mut data: Vec<u8> = Vec::new();
+            let mut oversized = false;
+            let mut data_line: Vec<u8> = Vec::new();
             
             loop {
-                line.clear();
-                let bytes_read = reader.read_line(&mut line).await?;
+                data_line.clear();
+                let bytes_read = stream.read_until(b'\n', &mut data_line).await?;
                 if bytes_read == 0 {
                     return Ok(());
                 }
                 
-                if line.trim() == "." {
+                if data_line.trim_ascii() == b"." {
                     break;
                 }
                 
-                if line.starts_with("..") {
-                    data_lines.push(line[1..].to_string());
+                // Keep reading until the terminator, but stop buffering once over the limit
+                if oversized {
+                    continue;
+                }
+                
+                if data_line.starts_with(b"..") {
+                    data.extend_from_slice(&data_line[1..]);
                 } else {
-                    data_lines.push(line.clone());
+                    data.extend_from_slice(&data_line);
+                }
+                
+                if max_size.is_some_and(|max| data.len() > max) {
+                    oversized = true;
+                    data.clear();
                 }
             }
             
-            let data = data_lines.join("");
-            let response = session.handle_data(data).await;
-            writer.write_all(response.as_bytes()).await?;
-            writer.flush().await?;
+            let response = if oversized {
+                session.reject_oversized()
+            } else {
+                session.handle_data(data).await
+            };
+            stream.write_all(response.as_bytes()).await?;
+            stream.flush().await?;
             
             if response.starts_with("221") {
                 return Ok(());
             }
         } else {
-            let bytes_read = reader.read_line(&mut line).await?;
+            let bytes_read = stream.read_line(&mut line).await?;
             if bytes_read == 0 {
                 return Ok(());
             }
 
             let trimmed = line.trim();
-            tracing::debug!("Received: {}", trimmed);
+            // Keep credentials out of the logs
+            if session.is_authenticating() || trimmed.to_uppercase().starts_with("AUTH ") {
+                tracing::debug!("Received: AUTH exchange");
+            } else {
+                tracing::debug!("Received: {}", trimmed);
+            }
 
             let response = session.handle_command(trimmed).await;
-            writer.write_all(response.as_bytes()).await?;
-            writer.flush().await?;
+            stream.write_all(response.as_bytes()).await?;
+            stream.flush().await?;
 
             if response.starts_with("221") {
                 return Ok(());
             }
+
+            if session.take_starttls() {
+                let TlsMode::StartTls { acceptor, .. } = &tls else {
+                    anyhow::bail!("STARTTLS accepted without a TLS acceptor");
+                };
+                // Dropping the buffer discards anything pipelined before the handshake (RFC 3207 section 4.2)
+                let inner = stream.into_inner();
+                let tls_stream = acceptor.accept(inner).await?;
+                tracing::info!("TLS established with {}", addr);
+
+                stream = BufStream::new(Box::new(tls_stream));
+                session.tls_established();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{DeliveryPolicy, TlsConfig};
+    use crate::routing::Router;
+    use crate::strategies::create_strategies;
+    use std::net::SocketAddr;
+    use std::time::Duration;
+    use tokio::net::TcpListener;
+    use tokio_native_tls::{native_tls, TlsConnector};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    /// Write a self-signed certificate and PKCS#8 key for `localhost`
+    fn tls_config(dir: &tempfile::TempDir, require_tls: bool) -> TlsConfig {
+        let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let cert_path = dir.path().join("cert.pem");
+        let key_path = dir.path().join("key.pem");
+        std::fs::write(&cert_path, certified.cert.pem()).unwrap();
+        std::fs::write(&key_path, certified.key_pair.serialize_pem()).unwrap();
+        TlsConfig {
+            cert_path: cert_path.to_string_lossy().into_owned(),
+            key_path: key_path.to_string_lossy().into_owned(),
+            require_tls,
+        }
+    }
+
+    /// Accept connections on a loopback port until the test ends
+    async fn serve(config: Config, tls: TlsMode) -> SocketAddr {
+        let config = Arc::new(config);
+        let strategies = Arc::new(create_strategies(config.strategies.clone()).unwrap());
+        let router = Router::new(&config, &strategies).unwrap();
+        let dispatcher = Arc::new(Dispatcher::new(strategies, router, None, DeliveryPolicy::All, Duration::from_secs(5)));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                tokio::spawn(handle_connection(stream, Arc::clone(&config), Arc::clone(&dispatcher), tls.clone()));
+            }
+        });
+        addr
+    }
+
+    async fn tls_connect(stream: TcpStream) -> tokio_native_tls::TlsStream<TcpStream> {
+        let connector = native_tls::TlsConnector::builder().danger_accept_invalid_certs(true).build().unwrap();
+        TlsConnector::from(connector).connect("localhost", stream).await.unwrap()
+    }
+
+    struct Client<S> {
+        stream: BufStream<S>,
+    }
+
+    impl<S: AsyncRead + AsyncWrite + Unpin> Client<S> {
+        async fn new(stream: S) -> (Self, String) {
+            let mut client = Self { stream: BufStream::new(stream) };
+            let greeting = client.reply().await;
+            (client, greeting)
+        }
+
+        /// Read one reply, including all continuation lines
+        async fn reply(&mut self) -> String {
+            let mut reply = String::new();
+            loop {
+                let mut line = String::new();
+                self.stream.read_line(&mut line).await.unwrap();
+                reply.push_str(&line);
+                if line.len() < 4 || line.as_bytes()[3] == b' ' {
+                    return reply;
+                }
+            }
+        }
+
+        async fn command(&mut self, line: &str) -> String {
+            self.stream.write_all(format!("{}\r\n", line).as_bytes()).await.unwrap();
+            self.stream.flush().await.unwrap();
+            self.reply().await
         }
     }
+
+    #[tokio::test]
+    async fn starttls_upgrades_the_connection() {
+        let dir = tempfile::tempdir().unwrap();
+        let tls_config = tls_config(&dir, true);
+        let tls = TlsMode::from_config(Some(&tls_config), false).unwrap();
+        let addr = serve(Config::default(), tls).await;
+
+        let (mut client, greeting) = Client::new(TcpStream::connect(addr).await.unwrap()).await;
+        assert!(greeting.starts_with("220 "));
+        assert!(client.command("EHLO client").await.ends_with("250 STARTTLS\r\n"));
+        assert!(client.command("MAIL FROM:<a@example.com>").await.starts_with("530 5.7.0"));
+        assert!(client.command("STARTTLS").await.starts_with("220 2.0.0"));
+
+        let stream = tls_connect(client.stream.into_inner()).await;
+        let mut client = Client { stream: BufStream::new(stream) };
+        let ehlo = client.command("EHLO client").await;
+        assert!(ehlo.starts_with("250-smtp-relay Hello"));
+        assert!(!ehlo.contains("STARTTLS"));
+        assert!(client.command("MAIL FROM:<a@example.com>").await.starts_with("250"));
+        assert!(client.command("QUIT").await.starts_with("221"));
+    }
+
+    #[tokio::test]
+    async fn implicit_tls_listener_handshakes_before_the_greeting() {
+        let dir = tempfile::tempdir().unwrap();
+        let tls_config = tls_config(&dir, false);
+        let tls = TlsMode::from_config(Some(&tls_config), true).unwrap();
+        let addr = serve(Config::default(), tls).await;
+
+        let stream = tls_connect(TcpStream::connect(addr).await.unwrap()).await;
+        let (mut client, greeting) = Client::new(stream).await;
+        assert!(greeting.starts_with("220 smtp-relay ESMTP"));
+        let ehlo = client.command("EHLO client").await;
+        assert!(ehlo.ends_with("250 ENHANCEDSTATUSCODES\r\n"));
+        assert!(!ehlo.contains("STARTTLS"));
+        assert!(client.command("STARTTLS").await.starts_with("502"));
+        assert!(client.command("MAIL FROM:<a@example.com>").await.starts_with("250"));
+    }
+
+    #[tokio::test]
+    async fn data_over_the_size_limit_is_rejected() {
+        let webhook = MockServer::start().await;
+        Mock::given(wiremock::matchers::method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&webhook)
+            .await;
+        let config: Config = serde_json::from_value(serde_json::json!({
+            "max_message_size": 100,
+            "strategies": [{ "type": "webhook", "api_url": webhook.uri() }],
+        }))
+        .unwrap();
+        let addr = serve(config, TlsMode::None).await;
+
+        let (mut client, _) = Client::new(TcpStream::connect(addr).await.unwrap()).await;
+        assert!(client.command("EHLO client").await.contains("250-SIZE 100\r\n"));
+
+        // Too large: the whole body is read and refused, and the transaction is reset
+        client.command("MAIL FROM:<a@example.com>").await;
+        client.command("RCPT TO:<b@example.com>").await;
+        assert!(client.command("DATA").await.starts_with("354"));
+        let body = format!("Subject: Big\r\n\r\n{}\r\n.", "x".repeat(200));
+        assert_eq!(client.command(&body).await, "552 5.3.4 Message size exceeds fixed maximum message size\r\n");
+        assert!(client.command("RCPT TO:<b@example.com>").await.starts_with("503"));
+
+        client.command("MAIL FROM:<a@example.com>").await;
+        client.command("RCPT TO:<b@example.com>").await;
+        client.command("DATA").await;
+        assert!(client.command("Subject: Small\r\n\r\nhi\r\n.").await.starts_with("250 2.0.0"));
+    }
 }
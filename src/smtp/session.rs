@@ -1,25 +1,101 @@
 use std::sync::Arc;
-use crate::strategies::{ApiStrategy, EmailData};
+use crate::config::{Config, RouteOverrides};
+use crate::delivery::{DispatchOutcome, Dispatcher};
+use crate::strategies::EmailData;
+use super::auth::{self, AuthState};
 
 pub struct SmtpSession {
     from: Option<String>,
     to: Vec<String>,
-    data: Option<String>,
+    data: Option<Vec<u8>>,
     pub expecting_data: bool,
-    strategies: Arc<Vec<ApiStrategy>>,
+    /// Whether STARTTLS can be offered on this connection
+    tls_available: bool,
+    /// Whether MAIL FROM is refused until STARTTLS
+    require_tls: bool,
+    /// Whether the connection is already encrypted
+    secure: bool,
+    starttls_pending: bool,
+    /// Username the client authenticated as
+    authenticated_user: Option<String>,
+    auth_state: Option<AuthState>,
+    config: Arc<Config>,
+    dispatcher: Arc<Dispatcher>,
 }
 
 impl SmtpSession {
-    pub fn new(strategies: Arc<Vec<ApiStrategy>>) -> Self {
+    pub fn new(config: Arc<Config>, dispatcher: Arc<Dispatcher>, tls_available: bool, require_tls: bool) -> Self {
         Self {
             from: None,
             to: Vec::new(),
             data: None,
             expecting_data: false,
-            strategies,
+            tls_available,
+            require_tls,
+            secure: false,
+            starttls_pending: false,
+            authenticated_user: None,
+            auth_state: None,
+            config,
+            dispatcher,
         }
     }
 
+    /// Returns true once if the last command asked to upgrade the connection to TLS
+    pub fn take_starttls(&mut self) -> bool {
+        std::mem::take(&mut self.starttls_pending)
+    }
+
+    /// Called after the TLS handshake; the client has to start over with EHLO (RFC 3207 section 4.2)
+    pub fn tls_established(&mut self) {
+        self.reset();
+        self.authenticated_user = None;
+        self.secure = true;
+    }
+
+    /// Whether the next line is a SASL response rather than a command
+    pub fn is_authenticating(&self) -> bool {
+        self.auth_state.is_some()
+    }
+
+    /// AUTH is offered once configured, and only over TLS unless explicitly allowed
+    fn auth_available(&self) -> bool {
+        self.config.auth.as_ref().is_some_and(|auth| self.secure || auth.allow_insecure)
+    }
+
+    fn require_auth(&self) -> bool {
+        self.config.auth.as_ref().is_some_and(|auth| auth.require_auth)
+    }
+
+    /// Largest message accepted in DATA, if limited
+    pub fn max_message_size(&self) -> Option<usize> {
+        match self.config.max_message_size {
+            0 => None,
+            size => Some(size),
+        }
+    }
+
+    /// ESMTP extensions advertised in the EHLO response
+    fn capabilities(&self) -> Vec<String> {
+        let mut capabilities = Vec::new();
+
+        match self.max_message_size() {
+            Some(size) => capabilities.push(format!("SIZE {}", size)),
+            None => capabilities.push("SIZE".to_string()),
+        }
+        capabilities.push("8BITMIME".to_string());
+        capabilities.push("PIPELINING".to_string());
+        capabilities.push("ENHANCEDSTATUSCODES".to_string());
+        if self.tls_available && !self.secure {
+            capabilities.push("STARTTLS".to_string());
+        }
+        if self.auth_available() {
+            capabilities.push(format!("AUTH {}", auth::MECHANISMS));
+        }
+
+        capabilities
+    }
+
     fn reset(&mut self) {
         self.from = None;
         self.to.clear();
@@ -28,69 +104,211 @@ impl SmtpSession {
     }
 
     pub async fn handle_command(&mut self, line: &str) -> String {
+        if let Some(state) = self.auth_state.take() {
+            return self.continue_auth(state, line).await;
+        }
+
         let parts: Vec<&str> = line.split_whitespace().collect();
         if parts.is_empty() {
-            return "500 Syntax error\r\n".to_string();
+            return "500 5.5.2 Syntax error\r\n".to_string();
         }
 
         let command = parts[0].to_uppercase();
+        let argument = line.trim()[parts[0].len()..].trim();
 
         match command.as_str() {
-            "EHLO" | "HELO" => {
+            "EHLO" => {
+                self.reset();
+                let mut lines = vec![format!("{} Hello", self.config.hostname)];
+                lines.extend(self.capabilities());
+                multiline_reply(250, &lines)
+            }
+            "HELO" => {
                 self.reset();
-                "250 Hello\r\n".to_string()
+                format!("250 {} Hello\r\n", self.config.hostname)
+            }
+            "STARTTLS" => {
+                if !self.tls_available {
+                    return "502 5.5.1 STARTTLS not available\r\n".to_string();
+                }
+                if self.secure {
+                    return "503 5.5.1 TLS already active\r\n".to_string();
+                }
+                if !argument.is_empty() {
+                    return "501 5.5.4 Syntax error, no parameters allowed\r\n".to_string();
+                }
+                self.starttls_pending = true;
+                "220 2.0.0 Ready to start TLS\r\n".to_string()
+            }
+            "AUTH" => {
+                self.start_auth(argument).await
             }
             "MAIL" => {
-                if parts.len() < 2 || !parts[1].to_uppercase().starts_with("FROM:") {
-                    return "500 Syntax error\r\n".to_string();
+                if self.require_tls && !self.secure {
+                    return "530 5.7.0 Must issue a STARTTLS command first\r\n".to_string();
+                }
+                if self.require_auth() && self.authenticated_user.is_none() {
+                    return "530 5.7.0 Authentication required\r\n".to_string();
+                }
+                let Some((from, params)) = parse_path(argument, "FROM:") else {
+                    return "501 5.5.4 Syntax error in MAIL command\r\n".to_string();
+                };
+                for param in params {
+                    let (key, value) = param.split_once('=').unwrap_or((param, ""));
+                    match key.to_uppercase().as_str() {
+                        "SIZE" => {
+                            let Ok(size) = value.parse::<usize>() else {
+                                return "501 5.5.4 Invalid SIZE parameter\r\n".to_string();
+                            };
+                            if self.max_message_size().is_some_and(|max| size > max) {
+                                return "552 5.3.4 Message size exceeds fixed maximum message size\r\n".to_string();
+                            }
+                        }
+                        "BODY" if matches!(value.to_uppercase().as_str(), "7BIT" | "8BITMIME") => {}
+                        _ => {
+                            return format!("555 5.5.4 Unsupported parameter {}\r\n", param);
+                        }
+                    }
+                }
+                if let (Some(auth_config), Some(username)) = (self.config.auth.as_ref(), self.authenticated_user.as_ref()) {
+                    if !auth::sender_allowed(auth_config, username, &from) {
+                        tracing::warn!("User {} is not allowed to send as {}", username, from);
+                        return format!("550 5.7.1 Sender address {} not allowed for user {}\r\n", from, username);
+                    }
                 }
-                let from = parts[1][5..].trim().trim_start_matches('<').trim_end_matches('>').to_string();
                 self.from = Some(from);
-                "250 OK\r\n".to_string()
+                "250 2.1.0 OK\r\n".to_string()
             }
             "RCPT" => {
                 if self.from.is_none() {
-                    return "503 Need MAIL command first\r\n".to_string();
+                    return "503 5.5.1 Need MAIL command first\r\n".to_string();
                 }
-                if parts.len() < 2 || !parts[1].to_uppercase().starts_with("TO:") {
-                    return "500 Syntax error\r\n".to_string();
+                let Some((to, _)) = parse_path(argument, "TO:") else {
+                    return "501 5.5.4 Syntax error in RCPT command\r\n".to_string();
+                };
+                if to.is_empty() {
+                    return "501 5.1.3 Empty recipient address\r\n".to_string();
                 }
-                let to = parts[1][3..].trim().trim_start_matches('<').trim_end_matches('>').to_string();
                 self.to.push(to);
-                "250 OK\r\n".to_string()
+                "250 2.1.5 OK\r\n".to_string()
             }
             "DATA" => {
                 if self.from.is_none() || self.to.is_empty() {
-                    return "503 Need MAIL and RCPT commands first\r\n".to_string();
+                    return "503 5.5.1 Need MAIL and RCPT commands first\r\n".to_string();
                 }
                 self.expecting_data = true;
                 "354 End data with <CR><LF>.<CR><LF>\r\n".to_string()
             }
             "QUIT" => {
-                "221 Bye\r\n".to_string()
+                "221 2.0.0 Bye\r\n".to_string()
             }
             "RSET" => {
                 self.reset();
-                "250 OK\r\n".to_string()
+                "250 2.0.0 OK\r\n".to_string()
             }
             "NOOP" => {
-                "250 OK\r\n".to_string()
+                "250 2.0.0 OK\r\n".to_string()
             }
             _ => {
-                "500 Command not recognized\r\n".to_string()
+                "500 5.5.2 Command not recognized\r\n".to_string()
             }
         }
     }
 
-    pub async fn handle_data(&mut self, data: String) -> String {
+    /// Handle `AUTH <mechanism> [initial-response]`
+    async fn start_auth(&mut self, argument: &str) -> String {
+        if self.config.auth.is_none() {
+            return "502 5.5.1 AUTH not available\r\n".to_string();
+        }
+        if !self.auth_available() {
+            return "538 5.7.11 Encryption required for requested authentication mechanism\r\n".to_string();
+        }
+        if self.authenticated_user.is_some() {
+            return "503 5.5.1 Already authenticated\r\n".to_string();
+        }
+        if self.from.is_some() {
+            return "503 5.5.1 AUTH not permitted during a mail transaction\r\n".to_string();
+        }
+
+        let mut args = argument.split_whitespace();
+        let mechanism = args.next().unwrap_or("").to_uppercase();
+        let initial_response = args.next();
+
+        match (mechanism.as_str(), initial_response) {
+            ("PLAIN", Some(response)) => self.continue_auth(AuthState::PlainResponse, response).await,
+            ("PLAIN", None) => {
+                self.auth_state = Some(AuthState::PlainResponse);
+                "334 \r\n".to_string()
+            }
+            ("LOGIN", Some(response)) => self.continue_auth(AuthState::LoginUsername, response).await,
+            ("LOGIN", None) => {
+                self.auth_state = Some(AuthState::LoginUsername);
+                // base64("Username:")
+                "334 VXNlcm5hbWU6\r\n".to_string()
+            }
+            ("", _) => "501 5.5.4 Syntax error in AUTH command\r\n".to_string(),
+            _ => "504 5.5.4 Unrecognized authentication type\r\n".to_string(),
+        }
+    }
+
+    /// Handle a client response during an AUTH exchange
+    async fn continue_auth(&mut self, state: AuthState, response: &str) -> String {
+        if response.trim() == "*" {
+            return "501 5.0.0 Authentication cancelled\r\n".to_string();
+        }
+
+        let (username, password) = match state {
+            AuthState::PlainResponse => match auth::parse_plain(response) {
+                Some(credentials) => credentials,
+                None => return "501 5.5.2 Cannot decode response\r\n".to_string(),
+            },
+            AuthState::LoginUsername => match auth::decode_response(response) {
+                Some(username) => {
+                    self.auth_state = Some(AuthState::LoginPassword { username });
+                    // base64("Password:")
+                    return "334 UGFzc3dvcmQ6\r\n".to_string();
+                }
+                None => return "501 5.5.2 Cannot decode response\r\n".to_string(),
+            },
+            AuthState::LoginPassword { username } => match auth::decode_response(response) {
+                Some(password) => (username, password),
+                None => return "501 5.5.2 Cannot decode response\r\n".to_string(),
+            },
+        };
+
+        let Some(auth_config) = self.config.auth.as_ref() else {
+            return "502 5.5.1 AUTH not available\r\n".to_string();
+        };
+
+        if auth::authenticate(auth_config, &username, &password).await {
+            tracing::info!("Client authenticated as {}", username);
+            self.authenticated_user = Some(username);
+            "235 2.7.0 Authentication successful\r\n".to_string()
+        } else {
+            tracing::warn!("Failed authentication attempt for {}", username);
+            "535 5.7.8 Authentication credentials invalid\r\n".to_string()
+        }
+    }
+
+    /// Reply sent when the message body exceeded `max_message_size`
+    pub fn reject_oversized(&mut self) -> String {
+        tracing::warn!("Rejected message exceeding {} bytes", self.config.max_message_size);
+        self.reset();
+        "552 5.3.4 Message size exceeds fixed maximum message size\r\n".to_string()
+    }
+
+    pub async fn handle_data(&mut self, data: Vec<u8>) -> String {
         tracing::info!("Received email data, length: {} bytes", data.len());
-        
+
+        // 8BITMIME bodies may not be valid UTF-8; the bytes are passed on unchanged
+        let text = String::from_utf8_lossy(&data).into_owned();
+
         // Log first 500 chars of raw data to see email structure
-        let preview: String = data.chars().take(500).collect();
+        let preview: String = text.chars().take(500).collect();
         tracing::debug!("Raw email data preview:\n{}", preview);
         
         // Log content type from headers
-        for line in data.lines().take(30) {
+        for line in text.lines().take(30) {
             if line.to_lowercase().starts_with("content-type:") {
                 tracing::info!("Email Content-Type: {}", line);
             }
@@ -103,37 +321,252 @@ impl SmtpSession {
         self.expecting_data = false;
 
         if let Some(ref from) = self.from {
-            let subject = extract_subject(self.data.as_ref().unwrap_or(&String::new()));
-            
+            let subject = extract_subject(&text);
+
             let email_data = EmailData {
                 from: from.clone(),
                 to: self.to.clone(),
                 subject,
-                body: self.data.clone().unwrap_or_default(),
+                body: text,
                 raw_data: self.data.clone().unwrap_or_default(),
+                authenticated_user: self.authenticated_user.clone(),
+                overrides: RouteOverrides::default(),
             };
 
-            // Send to all configured strategies
-            for strategy in self.strategies.iter() {
-                match strategy.send_email(email_data.clone()).await {
-                    Ok(()) => {
-                        tracing::info!("Email successfully forwarded via {} strategy", strategy.name());
-                    }
-                    Err(err) => {
-                        tracing::error!("Failed to forward email via {}: {}", strategy.name(), err);
-                    }
+            match self.dispatcher.dispatch(email_data).await {
+                DispatchOutcome::Accepted => {}
+                DispatchOutcome::Transient(reason) => {
+                    self.reset();
+                    return format!("451 4.3.0 {}, try again later\r\n", reason);
+                }
+                DispatchOutcome::Permanent(reason) => {
+                    self.reset();
+                    return format!("554 5.3.0 {}\r\n", reason);
                 }
             }
         }
 
         self.reset();
-        "250 OK\r\n".to_string()
+        "250 2.0.0 OK\r\n".to_string()
     }
 }
 
+/// Format a multi-line reply using `250-` continuation lines
+fn multiline_reply(code: u16, lines: &[String]) -> String {
+    let mut reply = String::new();
+    for (i, line) in lines.iter().enumerate() {
+        let separator = if i + 1 == lines.len() { ' ' } else { '-' };
+        reply.push_str(&format!("{}{}{}\r\n", code, separator, line));
+    }
+    reply
+}
+
+/// Parse a `FROM:<address> PARAM=value ...` style argument
+/// Returns the address and any trailing ESMTP parameters
+fn parse_path<'a>(argument: &'a str, prefix: &str) -> Option<(String, Vec<&'a str>)> {
+    // `get` rather than indexing, as the prefix length may fall inside a multi-byte character
+    if !argument.get(..prefix.len()).is_some_and(|start| start.eq_ignore_ascii_case(prefix)) {
+        return None;
+    }
+    let rest = argument[prefix.len()..].trim_start();
+
+    let (address, params) = if let Some(stripped) = rest.strip_prefix('<') {
+        let end = stripped.find('>')?;
+        (&stripped[..end], &stripped[end + 1..])
+    } else {
+        rest.split_once(' ').unwrap_or((rest, ""))
+    };
+
+    Some((address.trim().to_string(), params.split_whitespace().collect()))
+}
+
 fn extract_subject(data: &str) -> String {
     data.lines()
         .find(|line| line.to_lowercase().starts_with("subject:"))
         .map(|line| line.trim_start_matches("Subject:").trim().to_string())
         .unwrap_or_else(|| "No Subject".to_string())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{AuthConfig, UserConfig};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+    use crate::routing::Router;
+    use crate::strategies::create_strategies;
+    use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+    use std::time::Duration;
+
+    fn session(config: Config, tls_available: bool, require_tls: bool) -> SmtpSession {
+        let strategies = Arc::new(create_strategies(config.strategies.clone()).unwrap());
+        let router = Router::new(&config, &strategies).unwrap();
+        let dispatcher = Dispatcher::new(strategies, router, None, config.delivery_policy, Duration::from_secs(5));
+        SmtpSession::new(Arc::new(config), Arc::new(dispatcher), tls_available, require_tls)
+    }
+
+    fn auth_config(allow_insecure: bool) -> AuthConfig {
+        let alice = UserConfig {
+            username: "alice".to_string(),
+            password_hash: bcrypt::hash("secret", 4).unwrap(),
+            allowed_senders: Some(vec!["alice@example.com".to_string(), "*@alerts.example.com".to_string()]),
+        };
+        let bob = UserConfig { username: "bob".to_string(), password_hash: bcrypt::hash("hunter2", 4).unwrap(), allowed_senders: None };
+        AuthConfig { users: vec![alice, bob], require_auth: true, allow_insecure }
+    }
+
+    fn auth_session(allow_insecure: bool) -> SmtpSession {
+        session(Config { auth: Some(auth_config(allow_insecure)), ..Default::default() }, false, false)
+    }
+
+    async fn ehlo(session: &mut SmtpSession) -> Vec<String> {
+        let reply = session.handle_command("EHLO client.example.com").await;
+        reply.lines().map(|line| line[4..].to_string()).collect()
+    }
+
+    async fn login(session: &mut SmtpSession, username: &str, password: &str) -> String {
+        let response = BASE64.encode(format!("\0{}\0{}", username, password));
+        session.handle_command(&format!("AUTH PLAIN {}", response)).await
+    }
+
+    #[tokio::test]
+    async fn ehlo_advertises_size_and_8bitmime() {
+        let mut limited = session(Config { max_message_size: 1000, ..Default::default() }, false, false);
+        let reply = limited.handle_command("EHLO client.example.com").await;
+        assert_eq!(
+            reply,
+            "250-smtp-relay Hello\r\n250-SIZE 1000\r\n250-8BITMIME\r\n250-PIPELINING\r\n250 ENHANCEDSTATUSCODES\r\n"
+        );
+
+        // Without a limit SIZE is advertised without a value
+        let mut unlimited = session(Config { max_message_size: 0, ..Default::default() }, false, false);
+        assert!(ehlo(&mut unlimited).await.contains(&"SIZE".to_string()));
+    }
+
+    #[tokio::test]
+    async fn mail_size_parameter_is_checked_against_the_limit() {
+        let mut session = session(Config { max_message_size: 1000, ..Default::default() }, false, false);
+        assert!(session.handle_command("MAIL FROM:<a@example.com> SIZE=1001").await.starts_with("552 5.3.4"));
+        assert!(session.handle_command("MAIL FROM:<a@example.com> SIZE=1000 BODY=8BITMIME").await.starts_with("250"));
+        assert!(session.handle_command("MAIL FROM:<a@example.com> SMTPUTF8").await.starts_with("555"));
+    }
+
+    #[tokio::test]
+    async fn starttls_is_advertised_until_tls_is_established() {
+        let mut plain = session(Config::default(), false, false);
+        assert!(!ehlo(&mut plain).await.contains(&"STARTTLS".to_string()));
+        assert!(plain.handle_command("STARTTLS").await.starts_with("502"));
+
+        let mut session = session(Config::default(), true, true);
+        assert!(ehlo(&mut session).await.contains(&"STARTTLS".to_string()));
+        assert!(session.handle_command("MAIL FROM:<a@example.com>").await.starts_with("530 5.7.0 Must issue a STARTTLS"));
+        assert!(session.handle_command("STARTTLS").await.starts_with("220"));
+        assert!(session.take_starttls());
+
+        session.tls_established();
+        assert!(!ehlo(&mut session).await.contains(&"STARTTLS".to_string()));
+        assert!(session.handle_command("STARTTLS").await.starts_with("503"));
+        assert!(session.handle_command("MAIL FROM:<a@example.com>").await.starts_with("250"));
+    }
+
+    #[tokio::test]
+    async fn auth_is_only_advertised_over_tls_unless_allowed_insecure() {
+        let mut session = auth_session(false);
+        assert!(!ehlo(&mut session).await.iter().any(|line| line.starts_with("AUTH")));
+        assert!(login(&mut session, "alice", "secret").await.starts_with("538 5.7.11"));
+
+        session.tls_established();
+        assert!(ehlo(&mut session).await.contains(&"AUTH PLAIN LOGIN".to_string()));
+        assert!(login(&mut session, "alice", "secret").await.starts_with("235"));
+
+        let mut session = auth_session(true);
+        assert!(ehlo(&mut session).await.contains(&"AUTH PLAIN LOGIN".to_string()));
+    }
+
+    #[tokio::test]
+    async fn auth_plain_and_login_exchanges() {
+        let mut session = auth_session(true);
+        assert!(session.handle_command("MAIL FROM:<alice@example.com>").await.starts_with("530 5.7.0 Authentication required"));
+        assert!(login(&mut session, "alice", "wrong").await.starts_with("535"));
+        assert!(login(&mut session, "mallory", "secret").await.starts_with("535"));
+
+        // PLAIN with the response on its own line
+        assert_eq!(session.handle_command("AUTH PLAIN").await, "334 \r\n");
+        assert!(session.is_authenticating());
+        assert!(session.handle_command(&BASE64.encode("\0alice\0secret")).await.starts_with("235"));
+        assert!(session.handle_command("AUTH PLAIN").await.starts_with("503"));
+
+        let mut session = auth_session(true);
+        assert_eq!(session.handle_command("AUTH LOGIN").await, "334 VXNlcm5hbWU6\r\n");
+        assert_eq!(session.handle_command(&BASE64.encode("bob")).await, "334 UGFzc3dvcmQ6\r\n");
+        assert!(session.handle_command(&BASE64.encode("hunter2")).await.starts_with("235"));
+        assert!(session.handle_command("MAIL FROM:<bob@example.com>").await.starts_with("250"));
+
+        let mut session = auth_session(true);
+        assert!(session.handle_command("AUTH LOGIN").await.starts_with("334"));
+        assert!(session.handle_command("*").await.starts_with("501"));
+        assert!(!session.is_authenticating());
+        assert!(session.handle_command("AUTH CRAM-MD5").await.starts_with("504"));
+        assert!(session.handle_command("AUTH PLAIN !!!").await.starts_with("501"));
+    }
+
+    #[tokio::test]
+    async fn mail_from_is_limited_to_the_allowed_senders() {
+        let mut session = auth_session(true);
+        assert!(login(&mut session, "alice", "secret").await.starts_with("235"));
+
+        assert_eq!(
+            session.handle_command("MAIL FROM:<bob@example.com>").await,
+            "550 5.7.1 Sender address bob@example.com not allowed for user alice\r\n"
+        );
+        assert!(session.handle_command("MAIL FROM:<Alice@Example.com>").await.starts_with("250"));
+        assert!(session.handle_command("RSET").await.starts_with("250"));
+        assert!(session.handle_command("MAIL FROM:<cron@alerts.example.com>").await.starts_with("250"));
+
+        // No `allowed_senders`, so any address
+        let mut session = auth_session(true);
+        assert!(login(&mut session, "bob", "hunter2").await.starts_with("235"));
+        assert!(session.handle_command("MAIL FROM:<ceo@example.com>").await.starts_with("250"));
+    }
+
+    #[test]
+    fn parse_path_reads_address_and_parameters() {
+        assert_eq!(
+            parse_path("FROM:<a@example.com> SIZE=100 BODY=8BITMIME", "FROM:"),
+            Some(("a@example.com".to_string(), vec!["SIZE=100", "BODY=8BITMIME"]))
+        );
+        assert_eq!(parse_path("to: b@example.com", "TO:"), Some(("b@example.com".to_string(), vec![])));
+        assert_eq!(parse_path("FROM:<>", "FROM:"), Some((String::new(), vec![])));
+    }
+
+    #[test]
+    fn parse_path_rejects_other_prefixes_without_panicking() {
+        assert_eq!(parse_path("FROM\u{e9}:<a@b>", "FROM:"), None);
+        assert_eq!(parse_path("FRO\u{e9}:<a@b>", "FROM:"), None);
+        assert_eq!(parse_path("\u{1F600}", "TO:"), None);
+        assert_eq!(parse_path("FR", "FROM:"), None);
+    }
+
+    #[tokio::test]
+    async fn failed_deliveries_are_reported_to_the_client() {
+        let server = MockServer::start().await;
+        let config: Config = serde_json::from_value(serde_json::json!({
+            "delivery_policy": "all",
+            "strategies": [{ "type": "webhook", "api_url": server.uri() }],
+        }))
+        .unwrap();
+        let mut session = session(config, false, false);
+
+        for (status, reply) in [(503, "451 4.3.0 Delivery failed via webhook, try again later"), (400, "554 5.3.0 Delivery failed via webhook"), (200, "250 2.0.0 OK")] {
+            server.reset().await;
+            Mock::given(wiremock::matchers::method("POST"))
+                .respond_with(ResponseTemplate::new(status))
+                .mount(&server)
+                .await;
+
+            session.handle_command("MAIL FROM:<a@example.com>").await;
+            session.handle_command("RCPT TO:<b@example.com>").await;
+            session.handle_command("DATA").await;
+            assert_eq!(session.handle_data(b"Subject: Test\r\n\r\nhi\r\n".to_vec()).await, format!("{}\r\n", reply));
+        }
+    }
+}
@@ -0,0 +1,43 @@
+use tokio_native_tls::{native_tls, TlsAcceptor};
+
+use crate::config::TlsConfig;
+
+/// Build a TLS acceptor from the configured certificate and key
+pub fn load_acceptor(config: &TlsConfig) -> anyhow::Result<TlsAcceptor> {
+    let cert = std::fs::read(&config.cert_path)
+        .map_err(|err| anyhow::anyhow!("Failed to read TLS certificate {}: {}", config.cert_path, err))?;
+    let key = std::fs::read(&config.key_path)
+        .map_err(|err| anyhow::anyhow!("Failed to read TLS key {}: {}", config.key_path, err))?;
+
+    let identity = native_tls::Identity::from_pkcs8(&cert, &key)?;
+    let acceptor = native_tls::TlsAcceptor::new(identity)?;
+
+    Ok(TlsAcceptor::from(acceptor))
+}
+
+/// How TLS is offered on a listener
+#[derive(Clone)]
+pub enum TlsMode {
+    /// Plaintext only
+    None,
+    /// Plaintext greeting, upgraded with STARTTLS
+    StartTls { acceptor: TlsAcceptor, required: bool },
+    /// TLS handshake before the greeting (SMTPS)
+    Implicit(TlsAcceptor),
+}
+
+impl TlsMode {
+    /// Build the mode for a listener from its TLS configuration
+    pub fn from_config(config: Option<&TlsConfig>, implicit: bool) -> anyhow::Result<Self> {
+        let Some(config) = config else {
+            return Ok(TlsMode::None);
+        };
+
+        let acceptor = load_acceptor(config)?;
+        if implicit {
+            Ok(TlsMode::Implicit(acceptor))
+        } else {
+            Ok(TlsMode::StartTls { acceptor, required: config.require_tls })
+        }
+    }
+}
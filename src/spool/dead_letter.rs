@@ -0,0 +1,196 @@
+use std::path::Path;
+use tokio::fs;
+
+use super::{list_ids, load_message, sync_dir, unix_now, Delivery, DeliveryStatus, Spool, SpooledMessage};
+use crate::config::RouteOverrides;
+
+impl Spool {
+    /// Move a finished message with failed deliveries out of the queue
+    pub async fn dead_letter(&self, message: &SpooledMessage) -> anyhow::Result<()> {
+        self.move_message(message, &self.queue_dir, &self.dead_dir).await
+    }
+
+    /// Ids of all dead-lettered messages, oldest first
+    pub async fn list_dead(&self) -> anyhow::Result<Vec<String>> {
+        list_ids(&self.dead_dir).await
+    }
+
+    /// Load a dead-lettered message and its raw contents
+    pub async fn load_dead(&self, id: &str) -> anyhow::Result<(SpooledMessage, Vec<u8>)> {
+        load_message(&self.dead_dir, id)
+            .await
+            .map_err(|err| anyhow::anyhow!("Dead-lettered message {} not found: {}", id, err))
+    }
+
+    /// Put a dead-lettered message back into the queue
+    ///
+    /// With a strategy given, the message is delivered to that strategy only,
+    /// for the recipients routed to it or every recipient if it had none.
+    /// Otherwise every failed delivery is retried. The retry window starts over.
+    pub async fn replay(&self, id: &str, strategy: Option<&str>) -> anyhow::Result<()> {
+        let (mut message, _) = self.load_dead(id).await?;
+        let now = unix_now();
+
+        match strategy {
+            Some(strategy) => {
+                // Keep the deliveries originally routed to this strategy, if it had any
+                message.deliveries.retain(|d| d.strategy == strategy);
+                if message.deliveries.is_empty() {
+                    message.deliveries.push(Delivery {
+                        strategy: strategy.to_string(),
+                        legacy_index: None,
+                        recipients: message.to.clone(),
+                        overrides: RouteOverrides::default(),
+                        status: DeliveryStatus::Pending,
+                        attempts: 0,
+                        next_attempt_at: now,
+                        last_error: None,
+                    });
+                }
+                for delivery in message.deliveries.iter_mut() {
+                    delivery.status = DeliveryStatus::Pending;
+                    delivery.attempts = 0;
+                    delivery.next_attempt_at = now;
+                    delivery.last_error = None;
+                }
+            }
+            None => {
+                for delivery in message.deliveries.iter_mut().filter(|d| d.status == DeliveryStatus::Failed) {
+                    delivery.status = DeliveryStatus::Pending;
+                    delivery.attempts = 0;
+                    delivery.next_attempt_at = now;
+                    delivery.last_error = None;
+                }
+            }
+        }
+        message.received_at = now;
+
+        self.move_message(&message, &self.dead_dir, &self.queue_dir).await?;
+        self.notify.notify_one();
+
+        Ok(())
+    }
+
+    /// Move a message between spool directories with `message` as its new metadata
+    ///
+    /// The metadata is written at the destination first, then the contents are moved, then the
+    /// old metadata is removed, so a crash at any point leaves files `recover_moves` sorts out.
+    async fn move_message(&self, message: &SpooledMessage, from: &Path, to: &Path) -> anyhow::Result<()> {
+        let id = &message.id;
+        self.save_in(to, message).await?;
+        fs::rename(from.join(format!("{}.eml", id)), to.join(format!("{}.eml", id))).await?;
+        sync_dir(to).await?;
+        fs::remove_file(from.join(format!("{}.json", id))).await?;
+        sync_dir(from).await?;
+        Ok(())
+    }
+
+    /// Clean up after a crash in the middle of moving a message between `queue/` and `dead/`
+    ///
+    /// Metadata whose `.eml` sits in the other directory, next to metadata of its own, was
+    /// left behind by an interrupted move and is removed. Other metadata without an `.eml`
+    /// is reported; the spool skips it.
+    pub(super) fn recover_moves(&self) -> anyhow::Result<()> {
+        for (dir, other) in [(&self.queue_dir, &self.dead_dir), (&self.dead_dir, &self.queue_dir)] {
+            for entry in std::fs::read_dir(dir)? {
+                let path = entry?.path();
+                if path.extension().and_then(|e| e.to_str()) != Some("json") || path.with_extension("eml").exists() {
+                    continue;
+                }
+                let Some(id) = path.file_stem().and_then(|s| s.to_str()) else {
+                    continue;
+                };
+
+                if other.join(format!("{}.eml", id)).exists() && other.join(format!("{}.json", id)).exists() {
+                    tracing::warn!("Removing metadata of message {} left behind by an interrupted move", id);
+                    std::fs::remove_file(&path)?;
+                } else {
+                    tracing::error!("Spooled message {} has metadata but no contents, skipping it", id);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::SpoolConfig;
+    use crate::strategies::EmailData;
+
+    fn open(dir: &Path) -> Spool {
+        let config: SpoolConfig = serde_json::from_value(serde_json::json!({ "dir": dir })).unwrap();
+        Spool::open(config).unwrap()
+    }
+
+    async fn failed_message(spool: &Spool) -> SpooledMessage {
+        let email = EmailData::for_test(&["a@example.com"], "Subject: Test\r\n\r\nhi\r\n");
+        let id = spool.enqueue(&email, vec![("webhook".to_string(), RouteOverrides::default(), email.to.clone())]).await.unwrap();
+        let (mut message, _) = spool.load(&id).await.unwrap();
+        message.deliveries[0].status = DeliveryStatus::Failed;
+        message
+    }
+
+    #[tokio::test]
+    async fn dead_letter_and_replay_move_both_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let spool = open(dir.path());
+        let message = failed_message(&spool).await;
+
+        spool.dead_letter(&message).await.unwrap();
+        assert!(spool.list().await.unwrap().is_empty());
+        assert_eq!(spool.list_dead().await.unwrap(), std::slice::from_ref(&message.id));
+
+        spool.replay(&message.id, None).await.unwrap();
+        assert!(spool.list_dead().await.unwrap().is_empty());
+        let (replayed, raw) = spool.load(&message.id).await.unwrap();
+        assert_eq!(replayed.deliveries[0].status, DeliveryStatus::Pending);
+        assert_eq!(raw, b"Subject: Test\r\n\r\nhi\r\n");
+    }
+
+    #[tokio::test]
+    async fn interrupted_dead_letter_is_recovered_on_open() {
+        let dir = tempfile::tempdir().unwrap();
+        let spool = open(dir.path());
+        let message = failed_message(&spool).await;
+        let id = &message.id;
+
+        // Crashed after moving the contents but before removing the queue metadata
+        spool.save_in(&spool.dead_dir, &message).await.unwrap();
+        fs::rename(spool.queue_dir.join(format!("{}.eml", id)), spool.dead_dir.join(format!("{}.eml", id)))
+            .await
+            .unwrap();
+
+        let spool = open(dir.path());
+        assert!(!spool.queue_dir.join(format!("{}.json", id)).exists());
+        assert!(spool.list().await.unwrap().is_empty());
+        assert_eq!(spool.list_dead().await.unwrap(), std::slice::from_ref(id));
+    }
+
+    #[tokio::test]
+    async fn move_interrupted_before_the_contents_moved_is_rolled_back_on_open() {
+        let dir = tempfile::tempdir().unwrap();
+        let spool = open(dir.path());
+        let message = failed_message(&spool).await;
+
+        // Crashed right after writing the dead-letter metadata
+        spool.save_in(&spool.dead_dir, &message).await.unwrap();
+
+        let spool = open(dir.path());
+        assert!(spool.list_dead().await.unwrap().is_empty());
+        assert!(!spool.dead_dir.join(format!("{}.json", message.id)).exists());
+        assert_eq!(spool.list().await.unwrap(), std::slice::from_ref(&message.id));
+    }
+
+    #[tokio::test]
+    async fn metadata_without_contents_is_skipped() {
+        let dir = tempfile::tempdir().unwrap();
+        let spool = open(dir.path());
+        let message = failed_message(&spool).await;
+        fs::remove_file(spool.queue_dir.join(format!("{}.eml", message.id))).await.unwrap();
+
+        let spool = open(dir.path());
+        assert!(spool.list().await.unwrap().is_empty());
+    }
+}
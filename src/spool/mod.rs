@@ -0,0 +1,333 @@
+pub mod dead_letter;
+pub mod worker;
+
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::{fs, io::AsyncWriteExt, sync::Notify};
+
+use crate::config::{RouteOverrides, SpoolConfig};
+use crate::strategies::EmailData;
+
+/// Delivery state of a queued message for one strategy
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeliveryStatus {
+    Pending,
+    Delivered,
+    Failed,
+}
+
+/// Progress of a queued message towards one strategy
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(from = "StoredDelivery")]
+pub struct Delivery {
+    /// Id of the strategy
+    pub strategy: String,
+    /// Position of the strategy in the configuration, for queue files written before
+    /// strategies had ids; the worker replaces it with the id of that strategy
+    #[serde(skip)]
+    pub legacy_index: Option<usize>,
+    /// Recipients routed to this strategy; every recipient of the message when empty
+    pub recipients: Vec<String>,
+    /// Overrides of the route the recipients were sent through
+    #[serde(default, skip_serializing_if = "RouteOverrides::is_empty")]
+    pub overrides: RouteOverrides,
+    pub status: DeliveryStatus,
+    pub attempts: u32,
+    /// Unix timestamp of the next attempt
+    pub next_attempt_at: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+}
+
+/// A delivery as read from disk, in the current format or the one before strategy ids and routing
+#[derive(Deserialize)]
+struct StoredDelivery {
+    strategy: StoredStrategy,
+    #[serde(default)]
+    recipients: Vec<String>,
+    #[serde(default)]
+    overrides: RouteOverrides,
+    status: DeliveryStatus,
+    attempts: u32,
+    next_attempt_at: u64,
+    #[serde(default)]
+    last_error: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredStrategy {
+    Id(String),
+    Index(usize),
+}
+
+impl From<StoredDelivery> for Delivery {
+    fn from(stored: StoredDelivery) -> Self {
+        let (strategy, legacy_index) = match stored.strategy {
+            StoredStrategy::Id(id) => (id, None),
+            StoredStrategy::Index(index) => (index.to_string(), Some(index)),
+        };
+
+        Self {
+            strategy,
+            legacy_index,
+            recipients: stored.recipients,
+            overrides: stored.overrides,
+            status: stored.status,
+            attempts: stored.attempts,
+            next_attempt_at: stored.next_attempt_at,
+            last_error: stored.last_error,
+        }
+    }
+}
+
+/// Envelope and delivery state stored next to each queued message
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SpooledMessage {
+    pub id: String,
+    pub from: String,
+    pub to: Vec<String>,
+    pub subject: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub authenticated_user: Option<String>,
+    /// Unix timestamp the message was accepted at
+    pub received_at: u64,
+    pub deliveries: Vec<Delivery>,
+}
+
+impl SpooledMessage {
+    /// Whether every strategy has either succeeded or been given up on
+    pub fn is_finished(&self) -> bool {
+        self.deliveries.iter().all(|d| d.status != DeliveryStatus::Pending)
+    }
+
+    /// Whether any strategy permanently failed
+    pub fn has_failures(&self) -> bool {
+        self.deliveries.iter().any(|d| d.status == DeliveryStatus::Failed)
+    }
+
+    /// Rebuild the email handed to strategies from the stored envelope and raw message
+    pub fn email(&self, raw_data: Vec<u8>, delivery: &Delivery) -> EmailData {
+        EmailData {
+            from: self.from.clone(),
+            to: if delivery.recipients.is_empty() { self.to.clone() } else { delivery.recipients.clone() },
+            subject: self.subject.clone(),
+            body: String::from_utf8_lossy(&raw_data).into_owned(),
+            raw_data,
+            authenticated_user: self.authenticated_user.clone(),
+            overrides: delivery.overrides.clone(),
+        }
+    }
+}
+
+/// Durable message queue kept in a directory
+///
+/// Each message is stored as `queue/<id>.eml` with its envelope and delivery
+/// state in `queue/<id>.json`. Files are written to `tmp/` first and renamed
+/// into place, and the metadata is written last, so a crash never leaves a
+/// half-written message in the queue. Messages that permanently failed for
+/// at least one strategy are moved to `dead/`.
+pub struct Spool {
+    config: SpoolConfig,
+    queue_dir: PathBuf,
+    dead_dir: PathBuf,
+    tmp_dir: PathBuf,
+    notify: Notify,
+}
+
+impl Spool {
+    /// Open the spool directory, creating it if needed
+    pub fn open(config: SpoolConfig) -> anyhow::Result<Self> {
+        let root = PathBuf::from(&config.dir);
+        let queue_dir = root.join("queue");
+        let dead_dir = root.join("dead");
+        let tmp_dir = root.join("tmp");
+
+        for dir in [&queue_dir, &dead_dir, &tmp_dir] {
+            std::fs::create_dir_all(dir)
+                .map_err(|err| anyhow::anyhow!("Failed to create spool directory {}: {}", dir.display(), err))?;
+        }
+
+        let spool = Self {
+            config,
+            queue_dir,
+            dead_dir,
+            tmp_dir,
+            notify: Notify::new(),
+        };
+        spool.recover_moves()?;
+        Ok(spool)
+    }
+
+    pub fn config(&self) -> &SpoolConfig {
+        &self.config
+    }
+
+    /// Durably store a message for delivery to the given strategies with their route overrides and recipients
+    pub async fn enqueue(&self, email: &EmailData, targets: Vec<(String, RouteOverrides, Vec<String>)>) -> anyhow::Result<String> {
+        let id = uuid::Uuid::new_v4().simple().to_string();
+        let now = unix_now();
+
+        let message = SpooledMessage {
+            id: id.clone(),
+            from: email.from.clone(),
+            to: email.to.clone(),
+            subject: email.subject.clone(),
+            authenticated_user: email.authenticated_user.clone(),
+            received_at: now,
+            deliveries: targets
+                .into_iter()
+                .map(|(strategy, overrides, recipients)| Delivery {
+                    strategy,
+                    legacy_index: None,
+                    recipients,
+                    overrides,
+                    status: DeliveryStatus::Pending,
+                    attempts: 0,
+                    next_attempt_at: now,
+                    last_error: None,
+                })
+                .collect(),
+        };
+
+        self.write_atomic(&self.queue_dir.join(format!("{}.eml", id)), &email.raw_data).await?;
+        self.save(&message).await?;
+        self.notify.notify_one();
+
+        Ok(id)
+    }
+
+    /// Ids of all queued messages
+    pub async fn list(&self) -> anyhow::Result<Vec<String>> {
+        list_ids(&self.queue_dir).await
+    }
+
+    /// Load a queued message and its raw contents
+    pub async fn load(&self, id: &str) -> anyhow::Result<(SpooledMessage, Vec<u8>)> {
+        load_message(&self.queue_dir, id).await
+    }
+
+    /// Persist updated delivery state
+    pub async fn save(&self, message: &SpooledMessage) -> anyhow::Result<()> {
+        self.save_in(&self.queue_dir, message).await
+    }
+
+    async fn save_in(&self, dir: &Path, message: &SpooledMessage) -> anyhow::Result<()> {
+        let json = serde_json::to_vec_pretty(message)?;
+        self.write_atomic(&dir.join(format!("{}.json", message.id)), &json).await
+    }
+
+    /// Remove a message once all deliveries are finished
+    pub async fn remove(&self, id: &str) -> anyhow::Result<()> {
+        // Metadata goes first so a crash in between leaves an orphan .eml rather than a broken entry
+        fs::remove_file(self.queue_dir.join(format!("{}.json", id))).await?;
+        fs::remove_file(self.queue_dir.join(format!("{}.eml", id))).await?;
+        Ok(())
+    }
+
+    /// Wait until a message is enqueued or the poll interval elapses
+    pub async fn wait(&self) {
+        let interval = Duration::from_secs(self.config.poll_interval_secs.max(1));
+        tokio::select! {
+            _ = self.notify.notified() => {}
+            _ = tokio::time::sleep(interval) => {}
+        }
+    }
+
+    /// Delay before the next attempt after `attempts` failures
+    pub fn backoff(&self, attempts: u32) -> u64 {
+        let exponent = attempts.saturating_sub(1).min(64) as i32;
+        let delay = self.config.initial_backoff_secs as f64 * self.config.backoff_multiplier.powi(exponent);
+        (delay as u64).min(self.config.max_backoff_secs)
+    }
+
+    async fn write_atomic(&self, path: &Path, contents: &[u8]) -> anyhow::Result<()> {
+        let file_name = path
+            .file_name()
+            .ok_or_else(|| anyhow::anyhow!("Invalid spool path {}", path.display()))?;
+        let tmp_path = self.tmp_dir.join(file_name);
+
+        let mut file = fs::File::create(&tmp_path).await?;
+        file.write_all(contents).await?;
+        file.sync_all().await?;
+        drop(file);
+
+        fs::rename(&tmp_path, path).await?;
+        if let Some(dir) = path.parent() {
+            sync_dir(dir).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Make renames and removals in `dir` survive a crash
+async fn sync_dir(dir: &Path) -> anyhow::Result<()> {
+    fs::File::open(dir).await?.sync_all().await?;
+    Ok(())
+}
+
+/// Ids of all messages in a spool subdirectory, oldest first
+async fn list_ids(dir: &Path) -> anyhow::Result<Vec<String>> {
+    let mut entries = fs::read_dir(dir).await?;
+    let mut messages = Vec::new();
+
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        // Metadata without contents is reported when the spool is opened
+        if path.extension().and_then(|e| e.to_str()) != Some("json") || !fs::try_exists(path.with_extension("eml")).await? {
+            continue;
+        }
+        if let Some(id) = path.file_stem().and_then(|s| s.to_str()) {
+            let modified = entry.metadata().await?.modified().unwrap_or(UNIX_EPOCH);
+            messages.push((modified, id.to_string()));
+        }
+    }
+
+    messages.sort();
+    Ok(messages.into_iter().map(|(_, id)| id).collect())
+}
+
+async fn load_message(dir: &Path, id: &str) -> anyhow::Result<(SpooledMessage, Vec<u8>)> {
+    let json = fs::read(dir.join(format!("{}.json", id))).await?;
+    let message: SpooledMessage = serde_json::from_slice(&json)?;
+    let raw = fs::read(dir.join(format!("{}.eml", id))).await?;
+    Ok((message, raw))
+}
+
+/// Seconds since the Unix epoch
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn backoff_grows_by_the_multiplier_up_to_the_cap() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = json!({
+            "dir": dir.path(),
+            "initial_backoff_secs": 30,
+            "backoff_multiplier": 3.0,
+            "max_backoff_secs": 1000,
+        });
+        let spool = Spool::open(serde_json::from_value(config).unwrap()).unwrap();
+
+        let delays: Vec<u64> = (1..=6).map(|attempts| spool.backoff(attempts)).collect();
+        assert_eq!(delays, [30, 90, 270, 810, 1000, 1000]);
+        // No overflow however often a delivery failed
+        assert_eq!(spool.backoff(u32::MAX), 1000);
+
+        let config = json!({ "dir": dir.path(), "initial_backoff_secs": 10, "backoff_multiplier": 1.0 });
+        let spool = Spool::open(serde_json::from_value(config).unwrap()).unwrap();
+        assert_eq!(spool.backoff(1), 10);
+        assert_eq!(spool.backoff(20), 10);
+    }
+}
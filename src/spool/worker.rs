@@ -0,0 +1,240 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::{unix_now, DeliveryStatus, Spool, SpooledMessage};
+use crate::delivery::fan_out;
+use crate::strategies::{failure_kind, ApiStrategy, FailureKind};
+
+/// Deliver queued messages in the background until the process exits
+pub async fn run(spool: Arc<Spool>, strategies: Arc<Vec<ApiStrategy>>, timeout: Duration) {
+    loop {
+        if let Err(err) = process_queue(&spool, &strategies, timeout).await {
+            tracing::error!("Failed to process spool: {}", err);
+        }
+        spool.wait().await;
+    }
+}
+
+/// Attempt every delivery that is due
+async fn process_queue(spool: &Spool, strategies: &[ApiStrategy], timeout: Duration) -> anyhow::Result<()> {
+    for id in spool.list().await? {
+        let (mut message, raw_data) = match spool.load(&id).await {
+            Ok(loaded) => loaded,
+            Err(err) => {
+                tracing::error!("Failed to load spooled message {}: {}", id, err);
+                continue;
+            }
+        };
+
+        let now = unix_now();
+        let due = message
+            .deliveries
+            .iter()
+            .any(|d| d.status == DeliveryStatus::Pending && d.next_attempt_at <= now);
+        if !due {
+            continue;
+        }
+
+        deliver(spool, strategies, &mut message, raw_data, timeout).await;
+
+        if message.is_finished() && message.has_failures() {
+            spool.dead_letter(&message).await?;
+            tracing::error!("Spooled message {} moved to dead-letter store", message.id);
+        } else if message.is_finished() {
+            spool.remove(&message.id).await?;
+            tracing::info!("Spooled message {} finished", message.id);
+        } else {
+            spool.save(&message).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Attempt the due deliveries of one message at once and update their state
+async fn deliver(spool: &Spool, strategies: &[ApiStrategy], message: &mut SpooledMessage, raw_data: Vec<u8>, timeout: Duration) {
+    let max_age = spool.config().max_age_secs;
+    let now = unix_now();
+
+    // Queue files written before strategies had ids refer to them by position
+    for delivery in message.deliveries.iter_mut() {
+        if let Some(strategy) = delivery.legacy_index.take().and_then(|index| strategies.get(index)) {
+            delivery.strategy = strategy.id().to_string();
+        }
+    }
+
+    let mut due = Vec::new();
+    for (position, delivery) in message.deliveries.iter_mut().enumerate() {
+        if delivery.status != DeliveryStatus::Pending || delivery.next_attempt_at > now {
+            continue;
+        }
+        let Some(index) = strategies.iter().position(|s| s.id() == delivery.strategy) else {
+            tracing::error!("Spooled message {} refers to unknown strategy {}", message.id, delivery.strategy);
+            delivery.status = DeliveryStatus::Failed;
+            delivery.last_error = Some(format!("Strategy {} is no longer configured", delivery.strategy));
+            continue;
+        };
+        due.push((position, index));
+    }
+
+    let targets = due
+        .iter()
+        .map(|&(position, index)| (index, message.email(raw_data.clone(), &message.deliveries[position])))
+        .collect();
+    let results = fan_out(strategies, targets, timeout).await;
+    let now = unix_now();
+
+    // Results come back in the order of the targets
+    for ((position, _), (index, result)) in due.into_iter().zip(results) {
+        let name = strategies[index].id();
+        let delivery = &mut message.deliveries[position];
+        delivery.attempts += 1;
+
+        match result {
+            Ok(()) => {
+                tracing::info!("Spooled message {} delivered via {}", message.id, name);
+                delivery.status = DeliveryStatus::Delivered;
+                delivery.last_error = None;
+            }
+            Err(err) => {
+                delivery.last_error = Some(err.to_string());
+
+                if failure_kind(&err) == FailureKind::Permanent {
+                    tracing::error!(
+                        "Spooled message {} permanently rejected by {}: {}",
+                        message.id, name, err
+                    );
+                    delivery.status = DeliveryStatus::Failed;
+                } else if now.saturating_sub(message.received_at) >= max_age {
+                    tracing::error!(
+                        "Giving up on spooled message {} via {} after {} attempts: {}",
+                        message.id, name, delivery.attempts, err
+                    );
+                    delivery.status = DeliveryStatus::Failed;
+                } else {
+                    let delay = spool.backoff(delivery.attempts);
+                    tracing::warn!(
+                        "Delivery of spooled message {} via {} failed (attempt {}), retrying in {}s: {}",
+                        message.id, name, delivery.attempts, delay, err
+                    );
+                    delivery.next_attempt_at = now + delay;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::SpoolConfig;
+    use crate::strategies::{create_strategies, EmailData};
+    use serde_json::json;
+    use wiremock::matchers::body_partial_json;
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn spool(dir: &tempfile::TempDir, max_age_secs: u64) -> Spool {
+        let config = json!({ "dir": dir.path(), "initial_backoff_secs": 60, "max_age_secs": max_age_secs });
+        Spool::open(serde_json::from_value(config).unwrap()).unwrap()
+    }
+
+    fn webhook(server: &MockServer) -> Vec<ApiStrategy> {
+        create_strategies(vec![serde_json::from_value(json!({ "type": "webhook", "api_url": server.uri() })).unwrap()]).unwrap()
+    }
+
+    async fn failing_server(status: u16) -> MockServer {
+        let server = MockServer::start().await;
+        Mock::given(wiremock::matchers::method("POST"))
+            .respond_with(ResponseTemplate::new(status))
+            .expect(1)
+            .mount(&server)
+            .await;
+        server
+    }
+
+    #[tokio::test]
+    async fn transient_failures_are_retried_after_the_backoff() {
+        let server = failing_server(503).await;
+        let dir = tempfile::tempdir().unwrap();
+        let spool = spool(&dir, 3600);
+        let email = EmailData::for_test(&["a@example.com"], "Subject: Test\r\n\r\nhi\r\n");
+        let id = spool.enqueue(&email, vec![("webhook".to_string(), Default::default(), Vec::new())]).await.unwrap();
+
+        let before = unix_now();
+        process_queue(&spool, &webhook(&server), Duration::from_secs(5)).await.unwrap();
+        // Not due again yet, so the second pass does not try it
+        process_queue(&spool, &webhook(&server), Duration::from_secs(5)).await.unwrap();
+
+        let (message, _) = spool.load(&id).await.unwrap();
+        let delivery = &message.deliveries[0];
+        assert_eq!(delivery.status, DeliveryStatus::Pending);
+        assert_eq!(delivery.attempts, 1);
+        assert!(delivery.next_attempt_at >= before + 60);
+        assert!(delivery.last_error.as_deref().unwrap().contains("503"));
+    }
+
+    #[tokio::test]
+    async fn messages_past_max_age_are_moved_to_the_dead_letter_store() {
+        let server = failing_server(503).await;
+        let dir = tempfile::tempdir().unwrap();
+        let spool = spool(&dir, 3600);
+        let email = EmailData::for_test(&["a@example.com"], "Subject: Test\r\n\r\nhi\r\n");
+        let id = spool.enqueue(&email, vec![("webhook".to_string(), Default::default(), Vec::new())]).await.unwrap();
+
+        let (mut message, _) = spool.load(&id).await.unwrap();
+        message.received_at -= 3600;
+        spool.save(&message).await.unwrap();
+
+        process_queue(&spool, &webhook(&server), Duration::from_secs(5)).await.unwrap();
+
+        assert!(spool.list().await.unwrap().is_empty());
+        assert_eq!(spool.list_dead().await.unwrap(), std::slice::from_ref(&id));
+        let (message, raw_data) = spool.load_dead(&id).await.unwrap();
+        assert_eq!(message.deliveries[0].status, DeliveryStatus::Failed);
+        assert_eq!(message.deliveries[0].attempts, 1);
+        assert_eq!(raw_data, b"Subject: Test\r\n\r\nhi\r\n");
+    }
+
+    #[tokio::test]
+    async fn queue_files_from_before_strategy_ids_are_delivered() {
+        let server = MockServer::start().await;
+        Mock::given(body_partial_json(json!({ "to": ["a@example.com", "b@example.com"] })))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let config: SpoolConfig = serde_json::from_value(json!({ "dir": dir.path() })).unwrap();
+        let spool = Spool::open(config).unwrap();
+        let strategies = create_strategies(vec![
+            serde_json::from_value(json!({ "id": "first", "type": "webhook", "api_url": "http://127.0.0.1:9" })).unwrap(),
+            serde_json::from_value(json!({ "id": "second", "type": "webhook", "api_url": server.uri() })).unwrap(),
+        ])
+        .unwrap();
+
+        // Deliveries referred to strategies by position and had no recipients of their own
+        let queue = dir.path().join("queue");
+        std::fs::write(queue.join("legacy.eml"), "Subject: Test\r\n\r\nhi\r\n").unwrap();
+        std::fs::write(
+            queue.join("legacy.json"),
+            json!({
+                "id": "legacy",
+                "from": "sender@example.com",
+                "to": ["a@example.com", "b@example.com"],
+                "subject": "Test",
+                "received_at": unix_now(),
+                "deliveries": [
+                    { "strategy": 0, "status": "delivered", "attempts": 1, "next_attempt_at": 0 },
+                    { "strategy": 1, "status": "pending", "attempts": 0, "next_attempt_at": 0 },
+                ],
+            })
+            .to_string(),
+        )
+        .unwrap();
+
+        process_queue(&spool, &strategies, Duration::from_secs(5)).await.unwrap();
+
+        assert!(spool.list().await.unwrap().is_empty());
+    }
+}
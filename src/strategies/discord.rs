@@ -0,0 +1,315 @@
+use chrono::Utc;
+use reqwest::multipart::{Form, Part};
+
+use super::mime::{decode_header_value, parse_email, truncate, Attachment};
+use super::{DeliveryError, EmailData};
+use crate::config::DiscordConfig;
+
+/// Discord webhook strategy posting an embed per message, with the attachments uploaded as files
+/// https://discord.com/developers/docs/resources/webhook#execute-webhook
+///
+/// Content over Discord's limits is cut off and attachments that do not fit are left out,
+/// with a notice in the message, rather than failing the delivery. A route's `username`
+/// takes precedence over the configured one.
+#[derive(Debug, Clone)]
+pub struct DiscordStrategy {
+    id: String,
+    client: reqwest::Client,
+    webhook_url: String,
+    username: Option<String>,
+    max_upload_bytes: usize,
+}
+
+/// Discord limits, in characters unless noted
+/// https://discord.com/developers/docs/resources/message#embed-object-embed-limits
+const MAX_CONTENT_LEN: usize = 2000;
+const MAX_TITLE_LEN: usize = 256;
+const MAX_DESCRIPTION_LEN: usize = 4096;
+const MAX_AUTHOR_LEN: usize = 256;
+/// Below the footer's own limit, so the embed stays under the 6000 characters allowed in total
+const MAX_FOOTER_LEN: usize = 1024;
+const MAX_FILES: usize = 10;
+
+const TRUNCATED: &str = "… (truncated)";
+
+#[derive(serde::Serialize)]
+struct WebhookMessage {
+    #[serde(skip_serializing_if = "String::is_empty")]
+    content: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    username: Option<String>,
+    embeds: Vec<Embed>,
+    /// Keeps `@everyone` and other mentions in forwarded mail from pinging anyone
+    allowed_mentions: AllowedMentions,
+}
+
+#[derive(serde::Serialize)]
+struct Embed {
+    title: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+    author: EmbedAuthor,
+    footer: EmbedFooter,
+    timestamp: String,
+}
+
+#[derive(serde::Serialize)]
+struct EmbedAuthor {
+    name: String,
+}
+
+#[derive(serde::Serialize)]
+struct EmbedFooter {
+    text: String,
+}
+
+#[derive(serde::Serialize)]
+struct AllowedMentions {
+    parse: [&'static str; 0],
+}
+
+impl DiscordStrategy {
+    pub fn new(id: String, config: DiscordConfig) -> anyhow::Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(std::time::Duration::from_secs(60))
+            .build()?;
+
+        Ok(Self {
+            id,
+            client,
+            webhook_url: config.webhook_url,
+            username: config.username,
+            max_upload_bytes: config.max_upload_bytes,
+        })
+    }
+
+    pub async fn send_email(&self, email: EmailData) -> anyhow::Result<()> {
+        let parsed = parse_email(&email.raw_text());
+        let subject = decode_header_value(email.subject.trim());
+        let body = parsed.plain_text();
+        let (uploads, skipped) = self.select_uploads(parsed.attachments);
+
+        let mut content = String::new();
+        if !skipped.is_empty() {
+            let names: Vec<String> = skipped
+                .iter()
+                .map(|attachment| format!("{} ({})", attachment.filename, attachment.display_size()))
+                .collect();
+            content = truncate(
+                &format!("Attachments left out, over Discord's upload limits: {}", names.join(", ")),
+                MAX_CONTENT_LEN,
+                TRUNCATED,
+            );
+        }
+
+        let message = WebhookMessage {
+            content,
+            username: email.overrides.username.clone().or_else(|| self.username.clone()),
+            embeds: vec![Embed {
+                title: truncate(if subject.is_empty() { "(no subject)" } else { &subject }, MAX_TITLE_LEN, "…"),
+                description: (!body.is_empty()).then(|| truncate(&body, MAX_DESCRIPTION_LEN, TRUNCATED)),
+                author: EmbedAuthor { name: truncate(&email.from, MAX_AUTHOR_LEN, "…") },
+                footer: EmbedFooter {
+                    text: truncate(&format!("To: {}", email.to.join(", ")), MAX_FOOTER_LEN, TRUNCATED),
+                },
+                timestamp: Utc::now().to_rfc3339(),
+            }],
+            allowed_mentions: AllowedMentions { parse: [] },
+        };
+
+        let mut form = Form::new().text("payload_json", serde_json::to_string(&message)?);
+        for (index, attachment) in uploads.into_iter().enumerate() {
+            // Discord derives the type from the file name
+            let part = Part::bytes(attachment.content).file_name(attachment.filename);
+            form = form.part(format!("files[{}]", index), part);
+        }
+
+        let response = self.client
+            .post(&self.webhook_url)
+            .multipart(form)
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let text = response.text().await.unwrap_or_default();
+            return Err(DeliveryError::from_status(status, format!("Discord webhook request failed: {} - {}", status, text)).into());
+        }
+
+        tracing::info!("Discord strategy {} posted message from {}", self.id, email.from);
+        Ok(())
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Split the attachments into those that fit in one upload, in order, and those left out
+    fn select_uploads(&self, attachments: Vec<Attachment>) -> (Vec<Attachment>, Vec<Attachment>) {
+        let mut uploads = Vec::new();
+        let mut skipped = Vec::new();
+        let mut total = 0;
+
+        for attachment in attachments {
+            let size = attachment.content.len();
+            if uploads.len() < MAX_FILES && total + size <= self.max_upload_bytes {
+                total += size;
+                uploads.push(attachment);
+            } else {
+                skipped.push(attachment);
+            }
+        }
+
+        (uploads, skipped)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::RouteOverrides;
+    use crate::strategies::{failure_kind, FailureKind};
+    use serde_json::{json, Value};
+    use wiremock::matchers::method;
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn strategy(webhook_url: &str, max_upload_bytes: usize) -> DiscordStrategy {
+        let config = serde_json::from_value(json!({
+            "webhook_url": webhook_url,
+            "username": "Mail",
+            "max_upload_bytes": max_upload_bytes,
+        }))
+        .unwrap();
+        DiscordStrategy::new("discord".to_string(), config).unwrap()
+    }
+
+    fn attachment(filename: &str, size: usize) -> Attachment {
+        Attachment {
+            filename: filename.to_string(),
+            content: vec![b'x'; size],
+            content_type: None,
+            content_id: None,
+            inline: false,
+        }
+    }
+
+    fn names(attachments: &[Attachment]) -> Vec<&str> {
+        attachments.iter().map(|attachment| attachment.filename.as_str()).collect()
+    }
+
+    /// The parts of a multipart/form-data body, by name, with their file names
+    fn form_parts(body: &[u8]) -> Vec<(String, Option<String>, Vec<u8>)> {
+        let body = String::from_utf8_lossy(body);
+        let boundary = body.lines().next().unwrap().to_string();
+        body.split(boundary.as_str())
+            .filter_map(|part| {
+                let (headers, content) = part.split_once("\r\n\r\n")?;
+                let attribute = |name: &str| {
+                    let start = headers.find(&format!("{}=\"", name))? + name.len() + 2;
+                    Some(headers[start..].split('"').next()?.to_string())
+                };
+                Some((attribute("name")?, attribute("filename"), content.trim_end_matches("\r\n").as_bytes().to_vec()))
+            })
+            .collect()
+    }
+
+    async fn post(strategy: &DiscordStrategy, server: &MockServer, email: EmailData) -> Vec<(String, Option<String>, Vec<u8>)> {
+        strategy.send_email(email).await.unwrap();
+        let requests = server.received_requests().await.unwrap();
+        form_parts(&requests.last().unwrap().body)
+    }
+
+    fn payload(parts: &[(String, Option<String>, Vec<u8>)]) -> Value {
+        let (_, _, json) = parts.iter().find(|(name, _, _)| name == "payload_json").unwrap();
+        serde_json::from_slice(json).unwrap()
+    }
+
+    #[tokio::test]
+    async fn posts_an_embed_with_the_attachments_as_files() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST")).respond_with(ResponseTemplate::new(200)).expect(1).mount(&server).await;
+
+        let raw = "Subject: Report\r\nMIME-Version: 1.0\r\nContent-Type: multipart/mixed; boundary=b\r\n\r\n\
+                   --b\r\nContent-Type: text/plain\r\n\r\nSee attached @everyone\r\n\
+                   --b\r\nContent-Type: text/csv\r\nContent-Disposition: attachment; filename=report.csv\r\n\r\na,b\r\n\
+                   --b\r\nContent-Type: application/octet-stream\r\nContent-Disposition: attachment; filename=big.bin\r\n\
+                   Content-Transfer-Encoding: base64\r\n\r\nAAAAAAAAAAAAAAAAAAAA\r\n--b--\r\n";
+        let email = EmailData { subject: "Report".to_string(), ..EmailData::for_test(&["a@example.com"], raw) };
+        let parts = post(&strategy(&server.uri(), 10), &server, email).await;
+
+        let payload = payload(&parts);
+        assert_eq!(payload["username"], "Mail");
+        assert_eq!(payload["embeds"][0]["title"], "Report");
+        assert_eq!(payload["embeds"][0]["description"], "See attached @everyone");
+        assert_eq!(payload["embeds"][0]["author"]["name"], "sender@example.com");
+        assert_eq!(payload["embeds"][0]["footer"]["text"], "To: a@example.com");
+        // Mentions in the mail must not ping anyone
+        assert_eq!(payload["allowed_mentions"], json!({ "parse": [] }));
+        assert_eq!(payload["content"], "Attachments left out, over Discord's upload limits: big.bin (15 B)");
+
+        let files: Vec<_> = parts.iter().filter(|(name, _, _)| name != "payload_json").collect();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].0, "files[0]");
+        assert_eq!(files[0].1.as_deref(), Some("report.csv"));
+        assert_eq!(files[0].2, b"a,b");
+    }
+
+    #[tokio::test]
+    async fn route_username_and_long_fields_are_applied() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST")).respond_with(ResponseTemplate::new(200)).expect(1).mount(&server).await;
+
+        let recipients: Vec<String> = (0..100).map(|i| format!("recipient{}@example.com", i)).collect();
+        let recipients: Vec<&str> = recipients.iter().map(String::as_str).collect();
+        let raw = format!("Subject: x\r\n\r\n{}\r\n", "d".repeat(5000));
+        let email = EmailData {
+            subject: "s".repeat(300),
+            overrides: RouteOverrides { channel: None, username: Some("Cron".to_string()) },
+            ..EmailData::for_test(&recipients, &raw)
+        };
+        let payload = payload(&post(&strategy(&server.uri(), 1024), &server, email).await);
+
+        assert_eq!(payload["username"], "Cron");
+        assert!(payload.get("content").is_none());
+        let embed = &payload["embeds"][0];
+        let title = embed["title"].as_str().unwrap();
+        assert_eq!(title.chars().count(), MAX_TITLE_LEN);
+        assert!(title.ends_with("s…"));
+        let description = embed["description"].as_str().unwrap();
+        assert_eq!(description.chars().count(), MAX_DESCRIPTION_LEN);
+        assert!(description.ends_with("d… (truncated)"));
+        let footer = embed["footer"]["text"].as_str().unwrap();
+        assert!(footer.chars().count() <= MAX_FOOTER_LEN);
+        assert!(footer.starts_with("To: recipient0@example.com, ") && footer.ends_with("… (truncated)"));
+    }
+
+    #[test]
+    fn select_uploads_keeps_what_fits_in_order() {
+        let strategy = strategy("http://127.0.0.1:9", 100);
+
+        let (uploads, skipped) = strategy.select_uploads(vec![attachment("a", 60), attachment("b", 50), attachment("c", 40)]);
+        assert_eq!(names(&uploads), ["a", "c"]);
+        assert_eq!(names(&skipped), ["b"]);
+
+        let attachments = (0..12).map(|i| attachment(&format!("f{}", i), 1)).collect();
+        let (uploads, skipped) = strategy.select_uploads(attachments);
+        assert_eq!(uploads.len(), MAX_FILES);
+        assert_eq!(names(&skipped), ["f10", "f11"]);
+
+        let (uploads, skipped) = strategy.select_uploads(vec![attachment("exact", 100), attachment("empty", 0)]);
+        assert_eq!(names(&uploads), ["exact", "empty"]);
+        assert!(skipped.is_empty());
+    }
+
+    #[tokio::test]
+    async fn errors_are_classified_by_status() {
+        let server = MockServer::start().await;
+        for (status, kind) in [(400, FailureKind::Permanent), (429, FailureKind::Transient), (502, FailureKind::Transient)] {
+            server.reset().await;
+            Mock::given(method("POST")).respond_with(ResponseTemplate::new(status)).mount(&server).await;
+            let email = EmailData::for_test(&["a@example.com"], "Subject: Test\r\n\r\nhi\r\n");
+            let error = strategy(&server.uri(), 1024).send_email(email).await.unwrap_err();
+            assert_eq!(failure_kind(&error), kind, "status {}", status);
+        }
+    }
+}
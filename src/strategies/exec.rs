@@ -0,0 +1,230 @@
+use std::process::Stdio;
+use std::time::Duration;
+
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+use super::{DeliveryError, EmailData};
+use crate::config::ExecConfig;
+
+/// Hands each message to a local command, like a sendmail or procmail pipe
+///
+/// The raw message is written to stdin and the envelope is passed in `SENDER`,
+/// `RECIPIENTS` (comma separated), `SUBJECT` and `AUTHENTICATED_USER`. The relay's
+/// own environment holds credentials, so only `PATH` and the `pass_env` variables are
+/// passed on from it.
+#[derive(Debug, Clone)]
+pub struct ExecStrategy {
+    id: String,
+    program: String,
+    args: Vec<String>,
+    timeout: Duration,
+    tempfail_codes: Vec<i32>,
+    pass_env: Vec<String>,
+}
+
+/// How much of the command's stderr ends up in the error message
+const MAX_STDERR_LEN: usize = 500;
+
+/// Search path for commands when the relay itself runs without one
+const DEFAULT_PATH: &str = "/usr/local/bin:/usr/bin:/bin";
+
+impl ExecStrategy {
+    pub fn new(id: String, config: ExecConfig) -> anyhow::Result<Self> {
+        let mut command = config.command.into_iter();
+        let program = command
+            .next()
+            .filter(|program| !program.is_empty())
+            .ok_or_else(|| anyhow::anyhow!("Exec strategy {} needs a command", id))?;
+
+        Ok(Self {
+            id,
+            program,
+            args: command.collect(),
+            timeout: Duration::from_secs(config.timeout_secs),
+            tempfail_codes: config.tempfail_codes,
+            pass_env: config.pass_env,
+        })
+    }
+
+    pub async fn send_email(&self, email: EmailData) -> anyhow::Result<()> {
+        let mut command = Command::new(&self.program);
+        // Own process group, so a timeout also kills whatever a script started
+        #[cfg(unix)]
+        command.process_group(0);
+
+        command
+            .env_clear()
+            .env("PATH", std::env::var_os("PATH").unwrap_or_else(|| DEFAULT_PATH.into()));
+        for name in &self.pass_env {
+            if let Some(value) = std::env::var_os(name) {
+                command.env(name, value);
+            }
+        }
+
+        let mut child = command
+            .args(&self.args)
+            .env("SENDER", &email.from)
+            .env("RECIPIENTS", email.to.join(","))
+            .env("SUBJECT", &email.subject)
+            .env("AUTHENTICATED_USER", email.authenticated_user.as_deref().unwrap_or_default())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|err| DeliveryError::permanent(format!("Failed to run {}: {}", self.program, err)))?;
+
+        let pid = child.id();
+        let mut stdin = child.stdin.take().expect("stdin is piped");
+        let raw = email.raw_data;
+
+        let run = async move {
+            // Write and wait together so a command that stops reading cannot block us
+            let write = async move {
+                let result = stdin.write_all(&raw).await;
+                drop(stdin);
+                result
+            };
+            let (written, output) = tokio::join!(write, child.wait_with_output());
+            if let Err(err) = written {
+                // Commands may legitimately exit without reading the whole message
+                if err.kind() != std::io::ErrorKind::BrokenPipe {
+                    return Err(err);
+                }
+            }
+            output
+        };
+
+        let output = match tokio::time::timeout(self.timeout, run).await {
+            Ok(output) => output?,
+            Err(_) => {
+                #[cfg(unix)]
+                if let Some(pid) = pid {
+                    // SAFETY: kill has no memory safety requirements; a negative pid addresses the process group
+                    unsafe { libc::kill(-(pid as libc::pid_t), libc::SIGKILL) };
+                }
+                return Err(DeliveryError::transient(format!(
+                    "{} timed out after {:?} and was killed",
+                    self.program, self.timeout
+                )).into());
+            }
+        };
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        if !stdout.trim().is_empty() {
+            tracing::debug!("Exec strategy {} output: {}", self.id, stdout.trim());
+        }
+
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let stderr: String = stderr.trim().chars().take(MAX_STDERR_LEN).collect();
+
+        match output.status.code() {
+            Some(0) => {
+                tracing::info!("Exec strategy {} delivered message via {}", self.id, self.program);
+                Ok(())
+            }
+            Some(code) if self.tempfail_codes.contains(&code) => Err(DeliveryError::transient(format!(
+                "{} exited with temporary failure {}: {}",
+                self.program, code, stderr
+            )).into()),
+            Some(code) => Err(DeliveryError::permanent(format!(
+                "{} exited with {}: {}",
+                self.program, code, stderr
+            )).into()),
+            // Killed by a signal, e.g. by the OOM killer
+            None => Err(DeliveryError::transient(format!(
+                "{} was terminated by a signal: {}",
+                self.program, stderr
+            )).into()),
+        }
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::strategies::{failure_kind, FailureKind};
+    use serde_json::json;
+
+    /// Run `script` with `sh -c`, with any further configuration merged in
+    fn strategy(script: &str, extra: serde_json::Value) -> ExecStrategy {
+        let mut config = json!({ "command": ["sh", "-c", script] });
+        if let Some(extra) = extra.as_object() {
+            config.as_object_mut().unwrap().extend(extra.clone());
+        }
+        ExecStrategy::new("exec".to_string(), serde_json::from_value(config).unwrap()).unwrap()
+    }
+
+    fn email() -> EmailData {
+        EmailData::for_test(&["a@example.com", "b@example.com"], "Subject: Test\r\n\r\nBody\r\n")
+    }
+
+    /// HOME stands in for the credentials the relay's environment may hold; every test sets the same value
+    fn set_home() {
+        std::env::set_var("HOME", "/var/lib/smtp-relay");
+    }
+
+    #[tokio::test]
+    async fn command_gets_the_envelope_but_not_the_relay_environment() {
+        set_home();
+        let script = r#"test -z "$HOME" && test "$SENDER" = sender@example.com \
+            && test "$RECIPIENTS" = a@example.com,b@example.com && test "$SUBJECT" = Test && test -n "$PATH""#;
+
+        strategy(script, json!({})).send_email(email()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn pass_env_variables_are_passed_on() {
+        set_home();
+        let script = r#"test "$HOME" = /var/lib/smtp-relay"#;
+        strategy(script, json!({ "pass_env": ["HOME"] })).send_email(email()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn stdin_carries_the_raw_message() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("message");
+        let config: ExecConfig = serde_json::from_value(json!({ "command": ["sh", "-c", r#"cat > "$0""#, path] })).unwrap();
+        let strategy = ExecStrategy::new("exec".to_string(), config).unwrap();
+
+        let mut email = email();
+        email.raw_data = b"Subject: Caf\xe9\r\n\r\n8bit \xe9\r\n".to_vec();
+        strategy.send_email(email).await.unwrap();
+
+        assert_eq!(std::fs::read(&path).unwrap(), b"Subject: Caf\xe9\r\n\r\n8bit \xe9\r\n");
+    }
+
+    #[tokio::test]
+    async fn exit_codes_are_classified() {
+        let error = strategy("exit 75", json!({})).send_email(email()).await.unwrap_err();
+        assert_eq!(failure_kind(&error), FailureKind::Transient);
+
+        let error = strategy("echo 'no such mailbox' >&2; exit 67", json!({})).send_email(email()).await.unwrap_err();
+        assert_eq!(failure_kind(&error), FailureKind::Permanent);
+        assert!(error.to_string().ends_with("exited with 67: no such mailbox"), "{}", error);
+
+        let tempfail = json!({ "tempfail_codes": [42] });
+        let error = strategy("exit 42", tempfail.clone()).send_email(email()).await.unwrap_err();
+        assert_eq!(failure_kind(&error), FailureKind::Transient);
+        let error = strategy("exit 75", tempfail).send_email(email()).await.unwrap_err();
+        assert_eq!(failure_kind(&error), FailureKind::Permanent);
+
+        let error = strategy("kill -9 $$", json!({})).send_email(email()).await.unwrap_err();
+        assert_eq!(failure_kind(&error), FailureKind::Transient);
+    }
+
+    #[tokio::test]
+    async fn commands_are_killed_at_the_timeout() {
+        let started = std::time::Instant::now();
+        let error = strategy("sleep 30", json!({ "timeout_secs": 1 })).send_email(email()).await.unwrap_err();
+
+        assert_eq!(failure_kind(&error), FailureKind::Transient);
+        assert!(error.to_string().contains("timed out"));
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
+}
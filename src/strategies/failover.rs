@@ -0,0 +1,169 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use super::{failure_kind, ApiStrategy, DeliveryError, EmailData, FailureKind};
+
+/// Ordered group of strategies where each one is only tried if the previous ones failed
+///
+/// Every member has a circuit breaker: after `failure_threshold` consecutive
+/// transient failures it is moved to the back of the line for `cooldown`, so an outage
+/// at the primary provider does not add its timeout to every message.
+#[derive(Debug, Clone)]
+pub struct FailoverStrategy {
+    id: String,
+    members: Vec<Member>,
+}
+
+#[derive(Debug, Clone)]
+struct Member {
+    strategy: ApiStrategy,
+    circuit: Arc<CircuitBreaker>,
+}
+
+#[derive(Debug)]
+struct CircuitBreaker {
+    failure_threshold: u32,
+    cooldown: Duration,
+    state: Mutex<CircuitState>,
+}
+
+#[derive(Debug, Default)]
+struct CircuitState {
+    consecutive_failures: u32,
+    open_until: Option<Instant>,
+}
+
+impl CircuitBreaker {
+    fn is_open(&self) -> bool {
+        let state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.open_until.is_some_and(|until| Instant::now() < until)
+    }
+
+    fn record_success(&self) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        *state = CircuitState::default();
+    }
+
+    /// Returns true if this failure opened the circuit
+    fn record_failure(&self) -> bool {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.consecutive_failures += 1;
+        if state.consecutive_failures >= self.failure_threshold {
+            state.open_until = Some(Instant::now() + self.cooldown);
+            return true;
+        }
+        false
+    }
+}
+
+impl FailoverStrategy {
+    pub fn new(id: String, strategies: Vec<ApiStrategy>, failure_threshold: u32, cooldown: Duration) -> anyhow::Result<Self> {
+        if strategies.is_empty() {
+            anyhow::bail!("failover strategy {} needs at least one member strategy", id);
+        }
+
+        let members = strategies
+            .into_iter()
+            .map(|strategy| Member {
+                strategy,
+                circuit: Arc::new(CircuitBreaker {
+                    failure_threshold: failure_threshold.max(1),
+                    cooldown,
+                    state: Mutex::new(CircuitState::default()),
+                }),
+            })
+            .collect();
+
+        Ok(Self { id, members })
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub async fn send_email(&self, email: EmailData) -> anyhow::Result<()> {
+        // Members with an open circuit are only tried once every healthy member has failed
+        let (healthy, open): (Vec<&Member>, Vec<&Member>) = self.members.iter().partition(|m| !m.circuit.is_open());
+        for member in &open {
+            tracing::warn!("Circuit open for {}, trying it last", member.strategy.id());
+        }
+
+        let mut errors = Vec::new();
+        let mut kind = FailureKind::Permanent;
+
+        for member in healthy.into_iter().chain(open) {
+            let name = member.strategy.id();
+            // Boxed because failover groups are strategies themselves
+            match Box::pin(member.strategy.send_email(email.clone())).await {
+                Ok(()) => {
+                    member.circuit.record_success();
+                    if !errors.is_empty() {
+                        tracing::info!("Failover {} delivered email via {} after {} failure(s)", self.id, name, errors.len());
+                    }
+                    return Ok(());
+                }
+                Err(err) => {
+                    tracing::warn!("Failover member {} failed: {}", name, err);
+                    // A permanent failure is about this message, not the health of the provider
+                    if failure_kind(&err) == FailureKind::Transient {
+                        kind = FailureKind::Transient;
+                        if member.circuit.record_failure() {
+                            tracing::error!("Opening circuit for {} after repeated failures", name);
+                        }
+                    }
+                    errors.push(format!("{}: {}", name, err));
+                }
+            }
+        }
+
+        let message = format!("All members of failover {} failed ({})", self.id, errors.join("; "));
+        Err(DeliveryError { kind, message }.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::WebhookConfig;
+    use crate::strategies::webhook::WebhookStrategy;
+    use wiremock::matchers::method;
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    async fn failover_to(server: &MockServer, status: u16) -> FailoverStrategy {
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(status))
+            .mount(server)
+            .await;
+        let webhook = WebhookStrategy::new(
+            "primary".to_string(),
+            WebhookConfig { id: None, api_url: server.uri(), extra_headers: Vec::new() },
+        )
+        .unwrap();
+        FailoverStrategy::new("failover".to_string(), vec![ApiStrategy::Webhook(webhook)], 2, Duration::from_secs(60))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn transient_failures_open_the_circuit() {
+        let server = MockServer::start().await;
+        let failover = failover_to(&server, 503).await;
+
+        for _ in 0..2 {
+            let err = failover.send_email(EmailData::for_test(&["a@example.com"], "\r\nhi")).await.unwrap_err();
+            assert_eq!(failure_kind(&err), FailureKind::Transient);
+        }
+        assert!(failover.members[0].circuit.is_open());
+    }
+
+    #[tokio::test]
+    async fn permanent_failures_leave_the_circuit_closed() {
+        let server = MockServer::start().await;
+        let failover = failover_to(&server, 400).await;
+
+        for _ in 0..5 {
+            let err = failover.send_email(EmailData::for_test(&["a@example.com"], "\r\nhi")).await.unwrap_err();
+            assert_eq!(failure_kind(&err), FailureKind::Permanent);
+        }
+        assert!(!failover.members[0].circuit.is_open());
+    }
+}
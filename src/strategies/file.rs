@@ -0,0 +1,342 @@
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use chrono::Utc;
+
+use super::mime::{get_header, message_headers};
+use super::EmailData;
+use crate::config::{FileConfig, FileFormat, Rotation};
+
+/// Writes each message to a local Maildir or `.eml` directory, with the envelope in a JSON sidecar
+///
+/// Files are named `<timestamp>_<message id>`. The sidecar sits next to the `.eml` file, or in
+/// `envelopes/` inside a Maildir so mail clients do not see it.
+#[derive(Debug, Clone)]
+pub struct FileStrategy {
+    id: String,
+    dir: PathBuf,
+    format: FileFormat,
+    rotate: Option<Rotation>,
+    retention: Option<Duration>,
+    last_cleanup: Arc<Mutex<Option<Instant>>>,
+}
+
+/// Retention is enforced at most this often, after a message has been written
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+#[derive(serde::Serialize)]
+struct Envelope<'a> {
+    from: &'a str,
+    to: &'a [String],
+    subject: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    authenticated_user: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message_id: Option<&'a str>,
+    received_at: String,
+    file: String,
+}
+
+impl FileStrategy {
+    pub fn new(id: String, config: FileConfig) -> anyhow::Result<Self> {
+        let dir = PathBuf::from(&config.dir);
+        fs::create_dir_all(&dir)
+            .map_err(|err| anyhow::anyhow!("Failed to create directory {} for file strategy {}: {}", config.dir, id, err))?;
+
+        Ok(Self {
+            id,
+            dir,
+            format: config.format,
+            rotate: config.rotate,
+            retention: config.retention_days.map(|days| Duration::from_secs(days * 24 * 60 * 60)),
+            last_cleanup: Arc::new(Mutex::new(None)),
+        })
+    }
+
+    pub async fn send_email(&self, email: EmailData) -> anyhow::Result<()> {
+        let strategy = self.clone();
+        let path = tokio::task::spawn_blocking(move || strategy.store(&email)).await??;
+        tracing::info!("File strategy {} stored message at {}", self.id, path.display());
+
+        // Cleaning up a large directory can take a while, so it does not hold up the delivery
+        if self.cleanup_due() {
+            let strategy = self.clone();
+            tokio::task::spawn_blocking(move || match strategy.clean_up() {
+                Ok(removed) if removed > 0 => {
+                    tracing::info!("File strategy {} removed {} expired files", strategy.id, removed)
+                }
+                Ok(_) => {}
+                Err(err) => tracing::warn!("File strategy {} failed to apply retention: {}", strategy.id, err),
+            });
+        }
+
+        Ok(())
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Write the message and its envelope, returning the path of the message file
+    fn store(&self, email: &EmailData) -> anyhow::Result<PathBuf> {
+        let now = Utc::now();
+        let dir = match self.rotate {
+            Some(rotation) => self.dir.join(now.format(period_format(rotation)).to_string()),
+            None => self.dir.clone(),
+        };
+
+        let raw_text = email.raw_text();
+        let headers = message_headers(&raw_text).unwrap_or_default();
+        let message_id = Some(get_header(headers, "message-id"))
+            .map(|id| id.trim().trim_start_matches('<').trim_end_matches('>').to_string())
+            .filter(|id| !id.is_empty());
+        let base = format!(
+            "{}_{}",
+            now.format("%Y%m%dT%H%M%S%.6fZ"),
+            sanitize(message_id.as_deref().unwrap_or(&uuid::Uuid::new_v4().simple().to_string())),
+        );
+
+        let (message_path, envelope_path) = match self.format {
+            FileFormat::Eml => {
+                fs::create_dir_all(&dir)?;
+                let stem = unique_stem(&base, |stem| dir.join(format!("{}.eml", stem)).exists());
+                let message_path = dir.join(format!("{}.eml", stem));
+                write_atomic(&dir.join(format!(".{}.eml.tmp", stem)), &message_path, &email.raw_data)?;
+                (message_path, dir.join(format!("{}.json", stem)))
+            }
+            FileFormat::Maildir => {
+                for sub in ["tmp", "new", "cur", "envelopes"] {
+                    fs::create_dir_all(dir.join(sub))?;
+                }
+                // Clients rename messages into cur/, so the envelope is the reliable marker of a used name
+                let stem = unique_stem(&base, |stem| dir.join("envelopes").join(format!("{}.json", stem)).exists());
+                let message_path = dir.join("new").join(&stem);
+                write_atomic(&dir.join("tmp").join(&stem), &message_path, &email.raw_data)?;
+                (message_path, dir.join("envelopes").join(format!("{}.json", stem)))
+            }
+        };
+
+        let envelope = Envelope {
+            from: &email.from,
+            to: &email.to,
+            subject: &email.subject,
+            authenticated_user: email.authenticated_user.as_deref(),
+            message_id: message_id.as_deref(),
+            received_at: now.to_rfc3339(),
+            file: message_path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+        };
+        let tmp_path = envelope_path.with_extension("json.tmp");
+        write_atomic(&tmp_path, &envelope_path, &serde_json::to_vec_pretty(&envelope)?)?;
+
+        Ok(message_path)
+    }
+
+    fn cleanup_due(&self) -> bool {
+        if self.retention.is_none() {
+            return false;
+        }
+
+        let mut last_cleanup = self.last_cleanup.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if last_cleanup.is_some_and(|at| at.elapsed() < CLEANUP_INTERVAL) {
+            return false;
+        }
+        *last_cleanup = Some(Instant::now());
+        true
+    }
+
+    /// Delete messages and envelopes past the retention period, then rotated directories
+    /// left without messages
+    ///
+    /// Only files the strategy writes are considered, so anything else kept in `dir` survives.
+    fn clean_up(&self) -> anyhow::Result<usize> {
+        let Some(retention) = self.retention else {
+            return Ok(0);
+        };
+        let cutoff = SystemTime::now() - retention;
+        let mut removed = self.remove_expired(&self.dir, cutoff)?;
+
+        if let Some(rotation) = self.rotate {
+            let current = Utc::now().format(period_format(rotation)).to_string();
+            for entry in fs::read_dir(&self.dir)? {
+                let entry = entry?;
+                let name = entry.file_name();
+                if !entry.file_type()?.is_dir() || !is_period_name(&name.to_string_lossy(), &current) {
+                    continue;
+                }
+                removed += self.remove_expired(&entry.path(), cutoff)?;
+                if name != current.as_str() && !contains_files(&entry.path())? {
+                    fs::remove_dir_all(entry.path())?;
+                }
+            }
+        }
+
+        Ok(removed)
+    }
+
+    /// Delete expired messages and envelopes from one message directory (`dir` or a rotated one)
+    fn remove_expired(&self, dir: &Path, cutoff: SystemTime) -> anyhow::Result<usize> {
+        match self.format {
+            FileFormat::Eml => remove_older_than(dir, cutoff, |path| {
+                matches!(path.extension().and_then(|extension| extension.to_str()), Some("eml" | "json"))
+            }),
+            FileFormat::Maildir => {
+                let mut removed = 0;
+                for sub in ["tmp", "new", "cur", "envelopes"] {
+                    removed += remove_older_than(&dir.join(sub), cutoff, |_| true)?;
+                }
+                Ok(removed)
+            }
+        }
+    }
+}
+
+fn period_format(rotation: Rotation) -> &'static str {
+    match rotation {
+        Rotation::Hourly => "%Y-%m-%d-%H",
+        Rotation::Daily => "%Y-%m-%d",
+        Rotation::Monthly => "%Y-%m",
+    }
+}
+
+/// Keep message ids usable as file names (and free of the `:` Maildir reserves for flags)
+fn sanitize(message_id: &str) -> String {
+    message_id
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || "-._@+=".contains(c) { c } else { '_' })
+        .take(100)
+        .collect()
+}
+
+fn unique_stem(base: &str, taken: impl Fn(&str) -> bool) -> String {
+    let mut stem = base.to_string();
+    let mut n = 1;
+    while taken(&stem) {
+        stem = format!("{}_{}", base, n);
+        n += 1;
+    }
+    stem
+}
+
+fn write_atomic(tmp_path: &Path, path: &Path, contents: &[u8]) -> anyhow::Result<()> {
+    let mut file = fs::File::create(tmp_path)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    drop(file);
+
+    fs::rename(tmp_path, path)?;
+    Ok(())
+}
+
+/// Whether a directory name has the shape of a rotation period like `current`
+fn is_period_name(name: &str, current: &str) -> bool {
+    name.len() == current.len()
+        && name.bytes().zip(current.bytes()).all(|(a, b)| {
+            if b.is_ascii_digit() { a.is_ascii_digit() } else { a == b }
+        })
+}
+
+/// Delete the selected files directly in `dir` modified before `cutoff`; a missing `dir` has none
+fn remove_older_than(dir: &Path, cutoff: SystemTime, selected: impl Fn(&Path) -> bool) -> anyhow::Result<usize> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(err) => return Err(err.into()),
+    };
+
+    let mut removed = 0;
+    for entry in entries {
+        let entry = entry?;
+        let path = entry.path();
+        if entry.file_type()?.is_file() && selected(&path) && entry.metadata()?.modified()? < cutoff {
+            fs::remove_file(path)?;
+            removed += 1;
+        }
+    }
+
+    Ok(removed)
+}
+
+fn contains_files(dir: &Path) -> anyhow::Result<bool> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        if !file_type.is_dir() || contains_files(&entry.path())? {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strategy(dir: &Path, format: &str, rotate: Option<&str>) -> FileStrategy {
+        let config = serde_json::from_value(serde_json::json!({
+            "dir": dir,
+            "format": format,
+            "rotate": rotate,
+            "retention_days": 1,
+        }))
+        .unwrap();
+        FileStrategy::new("file".to_string(), config).unwrap()
+    }
+
+    /// Create a file last modified two days ago
+    fn old_file(path: &Path) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        let file = fs::File::create(path).unwrap();
+        file.set_modified(SystemTime::now() - Duration::from_secs(2 * 24 * 60 * 60)).unwrap();
+    }
+
+    #[test]
+    fn retention_only_removes_messages_and_envelopes() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["old.eml", "old.json", "notes.txt", "archive/old.eml"] {
+            old_file(&dir.path().join(name));
+        }
+        fs::write(dir.path().join("new.eml"), "").unwrap();
+
+        assert_eq!(strategy(dir.path(), "eml", None).clean_up().unwrap(), 2);
+
+        assert!(!dir.path().join("old.eml").exists());
+        assert!(!dir.path().join("old.json").exists());
+        assert!(dir.path().join("notes.txt").exists());
+        assert!(dir.path().join("archive/old.eml").exists());
+        assert!(dir.path().join("new.eml").exists());
+    }
+
+    #[test]
+    fn maildir_retention_leaves_other_files_alone() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["new/1", "cur/2:2,S", "envelopes/1.json", "dovecot-uidlist", ".Sent/cur/3"] {
+            old_file(&dir.path().join(name));
+        }
+
+        assert_eq!(strategy(dir.path(), "maildir", None).clean_up().unwrap(), 3);
+
+        assert!(dir.path().join("dovecot-uidlist").exists());
+        assert!(dir.path().join(".Sent/cur/3").exists());
+    }
+
+    #[test]
+    fn retention_removes_emptied_rotated_directories_only() {
+        let dir = tempfile::tempdir().unwrap();
+        old_file(&dir.path().join("2020-01-01/old.eml"));
+        old_file(&dir.path().join("2020-01-02/old.eml"));
+        old_file(&dir.path().join("2020-01-02/keep.txt"));
+        fs::create_dir_all(dir.path().join("empty")).unwrap();
+
+        assert_eq!(strategy(dir.path(), "eml", Some("daily")).clean_up().unwrap(), 2);
+
+        assert!(!dir.path().join("2020-01-01").exists());
+        assert!(dir.path().join("2020-01-02/keep.txt").exists());
+        assert!(dir.path().join("empty").exists());
+    }
+}
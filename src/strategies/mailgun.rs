@@ -0,0 +1,139 @@
+use super::{DeliveryError, EmailData};
+use crate::config::MailgunConfig;
+use reqwest::multipart::{Form, Part};
+
+/// Mailgun strategy uploading the original message to the `messages.mime` endpoint
+/// https://documentation.mailgun.com/docs/mailgun/api-reference/openapi-final/tag/Messages/
+///
+/// The message is sent as received instead of being re-parsed, so headers, MIME
+/// structure and attachments reach Mailgun unchanged.
+#[derive(Debug, Clone)]
+pub struct MailgunStrategy {
+    id: String,
+    client: reqwest::Client,
+    api_key: String,
+    url: String,
+}
+
+impl MailgunStrategy {
+    pub fn new(id: String, config: MailgunConfig) -> anyhow::Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(std::time::Duration::from_secs(30))
+            .build()?;
+
+        let api_url = config
+            .api_url
+            .unwrap_or_else(|| config.region.api_url().to_string());
+
+        Ok(Self {
+            id,
+            client,
+            api_key: config.api_key,
+            url: format!("{}/v3/{}/messages.mime", api_url.trim_end_matches('/'), config.domain),
+        })
+    }
+
+    pub async fn send_email(&self, email: EmailData) -> anyhow::Result<()> {
+        tracing::info!("Mailgun strategy {} processing email from: {}", self.id, email.from);
+
+        // `to` holds the envelope recipients; the To and Cc headers are left as they are
+        let message = Part::bytes(email.raw_data)
+            .file_name("message.mime")
+            .mime_str("message/rfc822")?;
+        let form = Form::new()
+            .text("to", email.to.join(","))
+            .part("message", message);
+
+        let response = self
+            .client
+            .post(&self.url)
+            .basic_auth("api", Some(&self.api_key))
+            .multipart(form)
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let text = response.text().await.unwrap_or_default();
+            return Err(DeliveryError::from_status(status, format!("Mailgun API request failed: {} - {}", status, text)).into());
+        }
+
+        let mailgun_response: serde_json::Value = response.json().await.unwrap_or_default();
+        tracing::info!(
+            "Mailgun email queued. ID: {}",
+            mailgun_response
+                .get("id")
+                .and_then(|v| v.as_str())
+                .unwrap_or("unknown")
+        );
+
+        Ok(())
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::strategies::{failure_kind, FailureKind};
+    use serde_json::json;
+    use wiremock::matchers::{header, method, path};
+    use wiremock::{Mock, MockServer, Request, ResponseTemplate};
+
+    fn strategy(server: &MockServer) -> MailgunStrategy {
+        let config = serde_json::from_value(json!({
+            "api_key": "key-123",
+            "domain": "mg.example.com",
+            "api_url": server.uri(),
+        }))
+        .unwrap();
+        MailgunStrategy::new("mailgun".to_string(), config).unwrap()
+    }
+
+    fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+        haystack.windows(needle.len()).any(|window| window == needle)
+    }
+
+    #[tokio::test]
+    async fn uploads_the_raw_message_with_the_envelope_recipients() {
+        let raw = b"To: a@example.com\r\nSubject: Caf\xe9\r\n\r\nna\xefve\r\n";
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v3/mg.example.com/messages.mime"))
+            // base64 of "api:key-123"
+            .and(header("authorization", "Basic YXBpOmtleS0xMjM="))
+            .and(move |request: &Request| {
+                contains(&request.body, b"name=\"to\"\r\n\r\na@example.com,hidden@example.com\r\n")
+                    && contains(&request.body, b"filename=\"message.mime\"\r\nContent-Type: message/rfc822")
+                    && contains(&request.body, raw)
+            })
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "id": "<1@mg.example.com>" })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let mut email = EmailData::for_test(&["a@example.com", "hidden@example.com"], "");
+        email.raw_data = raw.to_vec();
+        strategy(&server).send_email(email).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn errors_are_classified_by_status() {
+        let server = MockServer::start().await;
+        let email = || EmailData::for_test(&["a@example.com"], "Subject: Test\r\n\r\nHello\r\n");
+
+        for (status, kind) in [(401, FailureKind::Permanent), (429, FailureKind::Transient), (500, FailureKind::Transient)] {
+            server.reset().await;
+            Mock::given(method("POST"))
+                .respond_with(ResponseTemplate::new(status))
+                .mount(&server)
+                .await;
+
+            let err = strategy(&server).send_email(email()).await.unwrap_err();
+            assert_eq!(failure_kind(&err), kind, "status {}", status);
+        }
+    }
+}
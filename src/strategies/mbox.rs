@@ -0,0 +1,158 @@
+use std::fs::{self, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+
+use chrono::Utc;
+
+use super::EmailData;
+use crate::config::MboxConfig;
+
+/// Appends each message to an mbox file in the mboxrd format
+///
+/// Each append holds an exclusive `flock` on the file, so concurrent deliveries, and
+/// other writers that use `flock`, cannot interleave.
+#[derive(Debug, Clone)]
+pub struct MboxStrategy {
+    id: String,
+    path: PathBuf,
+}
+
+impl MboxStrategy {
+    pub fn new(id: String, config: MboxConfig) -> anyhow::Result<Self> {
+        let path = PathBuf::from(&config.path);
+        if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)
+                .map_err(|err| anyhow::anyhow!("Failed to create directory for mbox strategy {}: {}", id, err))?;
+        }
+
+        Ok(Self { id, path })
+    }
+
+    pub async fn send_email(&self, email: EmailData) -> anyhow::Result<()> {
+        let entry = format_entry(&email);
+        let path = self.path.clone();
+
+        tokio::task::spawn_blocking(move || -> anyhow::Result<()> {
+            let mut file = OpenOptions::new().read(true).append(true).create(true).open(&path)?;
+            file.lock()?;
+
+            // Messages have to be separated by a blank line, even if the last writer left the file without one
+            let len = file.metadata()?.len();
+            if len > 0 {
+                let mut tail = Vec::new();
+                file.seek(SeekFrom::End(-(len.min(2) as i64)))?;
+                file.read_to_end(&mut tail)?;
+                let separator: &[u8] = match tail.as_slice() {
+                    [.., b'\n', b'\n'] => b"",
+                    [.., b'\n'] if len > 1 => b"\n",
+                    [b'\n'] => b"",
+                    _ => b"\n\n",
+                };
+                file.write_all(separator)?;
+            }
+
+            file.write_all(&entry)?;
+            file.sync_data()?;
+            // The lock is released when the file is closed
+            Ok(())
+        })
+        .await??;
+
+        tracing::info!("Mbox strategy {} appended message to {}", self.id, self.path.display());
+        Ok(())
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+}
+
+/// `From ` separator line, the message with LF line endings and `>From` quoting, and a blank line
+fn format_entry(email: &EmailData) -> Vec<u8> {
+    let sender = if email.from.is_empty() { "MAILER-DAEMON" } else { email.from.as_str() };
+    let mut entry = format!("From {} {}\n", sender, Utc::now().format("%a %b %e %H:%M:%S %Y")).into_bytes();
+
+    for line in email.raw_data.split_inclusive(|&b| b == b'\n') {
+        let line = line.strip_suffix(b"\n").unwrap_or(line);
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+
+        // mboxrd: quote "From " lines with any number of leading '>' so readers can undo it
+        let unquoted = &line[line.iter().take_while(|&&b| b == b'>').count()..];
+        if unquoted.starts_with(b"From ") {
+            entry.push(b'>');
+        }
+        entry.extend_from_slice(line);
+        entry.push(b'\n');
+    }
+
+    entry.push(b'\n');
+    entry
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_entry_quotes_from_lines_and_keeps_8bit_bytes() {
+        let email = EmailData {
+            from: "a@example.com".to_string(),
+            to: vec!["b@example.com".to_string()],
+            subject: "caf\u{e9}".to_string(),
+            body: String::new(),
+            raw_data: b"Subject: caf\xe9\r\n\r\nna\xefve\r\nFrom here\r\n>From there\r\n".to_vec(),
+            authenticated_user: None,
+            overrides: Default::default(),
+        };
+
+        let entry = format_entry(&email);
+        let (separator, message) = entry.split_at(entry.iter().position(|&b| b == b'\n').unwrap() + 1);
+
+        assert!(separator.starts_with(b"From a@example.com "));
+        assert_eq!(message, b"Subject: caf\xe9\n\nna\xefve\n>From here\n>>From there\n\n");
+    }
+
+    /// File contents with the dates of the `From ` lines left out
+    fn read_without_dates(path: &std::path::Path) -> String {
+        let contents = String::from_utf8(fs::read(path).unwrap()).unwrap();
+        contents
+            .split_inclusive('\n')
+            .map(|line| match line.strip_prefix("From ") {
+                Some(rest) => format!("From {}\n", rest.split(' ').next().unwrap()),
+                None => line.to_string(),
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn appends_are_separated_by_a_blank_line() {
+        let email = EmailData::for_test(&["a@example.com"], "Subject: Test\r\n\r\nBody\r\n");
+
+        // Existing files without a trailing newline, with one and with a blank line
+        for (existing, separator) in [("old body", "\n\n"), ("old body\n", "\n"), ("old body\n\n", ""), ("\n", "")] {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("mail").join("inbox");
+            let config: MboxConfig = serde_json::from_value(serde_json::json!({ "path": path })).unwrap();
+            let strategy = MboxStrategy::new("mbox".to_string(), config).unwrap();
+            fs::write(&path, existing).unwrap();
+
+            strategy.send_email(email.clone()).await.unwrap();
+            strategy.send_email(email.clone()).await.unwrap();
+
+            let entry = "From sender@example.com\nSubject: Test\n\nBody\n\n";
+            assert_eq!(read_without_dates(&path), format!("{}{}{}{}", existing, separator, entry, entry), "{:?}", existing);
+        }
+    }
+
+    #[tokio::test]
+    async fn first_append_creates_the_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("inbox");
+        let config: MboxConfig = serde_json::from_value(serde_json::json!({ "path": path })).unwrap();
+        let strategy = MboxStrategy::new("mbox".to_string(), config).unwrap();
+
+        strategy.send_email(EmailData::for_test(&["a@example.com"], "Subject: Test\r\n\r\nBody")).await.unwrap();
+
+        assert_eq!(read_without_dates(&path), "From sender@example.com\nSubject: Test\n\nBody\n\n");
+    }
+}
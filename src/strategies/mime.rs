@@ -0,0 +1,675 @@
+//! Minimal MIME parsing shared by the strategies that rebuild a message for a provider API
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+
+/// Text, HTML and attachments extracted from a raw message
+#[derive(Debug, Default)]
+pub struct ParsedEmail {
+    pub text: Option<String>,
+    pub html: Option<String>,
+    pub attachments: Vec<Attachment>,
+}
+
+/// A decoded attachment or inline part
+#[derive(Debug)]
+pub struct Attachment {
+    pub filename: String,
+    pub content: Vec<u8>,
+    /// Full `Content-Type` header value, including parameters
+    pub content_type: Option<String>,
+    /// `Content-ID` without the angle brackets, for parts referenced as `cid:` from HTML
+    pub content_id: Option<String>,
+    /// Whether the part is meant to be displayed inline rather than as a download
+    pub inline: bool,
+}
+
+impl Attachment {
+    /// Media type without parameters (`image/png`)
+    pub fn mime_type(&self) -> Option<&str> {
+        self.content_type
+            .as_deref()
+            .map(|ct| ct.split(';').next().unwrap_or(ct).trim())
+            .filter(|ct| !ct.is_empty())
+    }
+
+    pub fn content_base64(&self) -> String {
+        BASE64.encode(&self.content)
+    }
+
+    /// Human readable size of the decoded content (`12 KB`)
+    pub fn display_size(&self) -> String {
+        match self.content.len() {
+            bytes @ 0..1024 => format!("{} B", bytes),
+            bytes @ 1024..1_048_576 => format!("{:.0} KB", bytes as f64 / 1024.0),
+            bytes => format!("{:.1} MB", bytes as f64 / 1_048_576.0),
+        }
+    }
+}
+
+impl ParsedEmail {
+    /// The text body with LF line endings, or the HTML body converted to text for messages without one
+    pub fn plain_text(&self) -> String {
+        match (&self.text, &self.html) {
+            (Some(text), _) if !text.trim().is_empty() => text.trim().replace("\r\n", "\n"),
+            (_, Some(html)) => html_to_text(html),
+            (text, None) => text.as_deref().unwrap_or_default().trim().replace("\r\n", "\n"),
+        }
+    }
+}
+
+/// A mailbox from an address header
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Address {
+    pub name: Option<String>,
+    pub email: String,
+}
+
+/// Envelope recipients sorted by the header they appear in
+#[derive(Debug, Default)]
+pub struct Recipients {
+    pub to: Vec<Address>,
+    pub cc: Vec<Address>,
+    /// Recipients in neither `To` nor `Cc`
+    pub bcc: Vec<Address>,
+}
+
+/// Parse email and extract content
+pub fn parse_email(raw_data: &str) -> ParsedEmail {
+    // Split headers from body
+    let Some((headers, body)) = split_headers_body(raw_data) else {
+        return ParsedEmail { text: Some(raw_data.to_string()), ..Default::default() };
+    };
+
+    let mut parsed = ParsedEmail::default();
+    let mut text_parts = Vec::new();
+    let mut html_parts = Vec::new();
+    parse_part(headers, body, &mut text_parts, &mut html_parts, &mut parsed.attachments);
+
+    if !text_parts.is_empty() {
+        parsed.text = Some(text_parts.join("\n\n"));
+    }
+    if !html_parts.is_empty() {
+        parsed.html = Some(html_parts.join("<br><br>"));
+    }
+
+    parsed
+}
+
+/// Headers of a raw message, or `None` if it has no header section
+pub fn message_headers(raw_data: &str) -> Option<&str> {
+    split_headers_body(raw_data).map(|(headers, _)| headers)
+}
+
+/// Split email into headers and body
+pub fn split_headers_body(raw_data: &str) -> Option<(&str, &str)> {
+    if let Some(pos) = raw_data.find("\r\n\r\n") {
+        return Some((&raw_data[..pos], &raw_data[pos + 4..]));
+    }
+    if let Some(pos) = raw_data.find("\n\n") {
+        return Some((&raw_data[..pos], &raw_data[pos + 2..]));
+    }
+    None
+}
+
+/// Get header value (case-insensitive), with folded continuation lines joined
+pub fn get_header(headers: &str, name: &str) -> String {
+    let prefix = format!("{}:", name.to_lowercase());
+    let mut lines = headers.lines();
+
+    while let Some(line) = lines.next() {
+        if !line.to_lowercase().starts_with(&prefix) {
+            continue;
+        }
+
+        let mut value = line[prefix.len()..].trim().to_string();
+        for continuation in lines.by_ref() {
+            if !continuation.starts_with([' ', '\t']) {
+                break;
+            }
+            value.push(' ');
+            value.push_str(continuation.trim());
+        }
+        return value;
+    }
+
+    String::new()
+}
+
+/// Parse an address list header value (`"Name" <a@example.com>, b@example.com`)
+pub fn parse_addresses(value: &str) -> Vec<Address> {
+    let mut entries = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+
+    for ch in value.chars() {
+        match ch {
+            '"' => {
+                quoted = !quoted;
+                current.push(ch);
+            }
+            ',' if !quoted => entries.push(std::mem::take(&mut current)),
+            _ => current.push(ch),
+        }
+    }
+    entries.push(current);
+
+    entries.iter().filter_map(|entry| parse_address(entry)).collect()
+}
+
+fn parse_address(entry: &str) -> Option<Address> {
+    let entry = entry.trim();
+    if entry.is_empty() {
+        return None;
+    }
+
+    match (entry.rfind('<'), entry.rfind('>')) {
+        (Some(start), Some(end)) if start < end => {
+            let name = entry[..start].trim().trim_matches('"').trim();
+            Some(Address {
+                name: (!name.is_empty()).then(|| name.to_string()),
+                email: entry[start + 1..end].trim().to_string(),
+            })
+        }
+        _ => Some(Address { name: None, email: entry.to_string() }),
+    }
+}
+
+/// Sort the envelope recipients into to, cc and bcc according to the message headers,
+/// so APIs that generate their own headers do not reveal Bcc recipients
+///
+/// Duplicate recipients are dropped. Providers require at least one To recipient, so when
+/// none of the recipients appear in the `To` header a single one is promoted to To: the
+/// first Cc recipient, who is visible anyway, or else the first Bcc recipient.
+pub fn sort_recipients(recipients: &[String], headers: &str) -> Recipients {
+    let to_header = parse_addresses(&get_header(headers, "to"));
+    let cc_header = parse_addresses(&get_header(headers, "cc"));
+    let find = |list: &[Address], recipient: &str| {
+        list.iter()
+            .find(|address| address.email.eq_ignore_ascii_case(recipient))
+            .cloned()
+    };
+
+    let mut sorted = Recipients::default();
+    let mut seen: Vec<String> = Vec::new();
+    for recipient in recipients {
+        let key = recipient.to_lowercase();
+        if seen.contains(&key) {
+            continue;
+        }
+        seen.push(key);
+
+        if let Some(address) = find(&to_header, recipient) {
+            sorted.to.push(address);
+        } else if let Some(address) = find(&cc_header, recipient) {
+            sorted.cc.push(address);
+        } else {
+            sorted.bcc.push(Address { name: None, email: recipient.clone() });
+        }
+    }
+
+    if sorted.to.is_empty() {
+        if !sorted.cc.is_empty() {
+            sorted.to.push(sorted.cc.remove(0));
+        } else if !sorted.bcc.is_empty() {
+            sorted.to.push(sorted.bcc.remove(0));
+        }
+    }
+
+    sorted
+}
+
+/// Decode RFC 2047 encoded words (`=?UTF-8?B?...?=`) in a header value for display
+///
+/// Only UTF-8, US-ASCII and ISO-8859-1 are decoded; words in other charsets are kept as they are.
+pub fn decode_header_value(value: &str) -> String {
+    let mut decoded = String::with_capacity(value.len());
+    let mut rest = value;
+    let mut after_word = false;
+
+    while let Some(start) = rest.find("=?") {
+        let word = &rest[start..];
+        let Some((text, len)) = encoded_word_end(word).and_then(|end| Some((decode_encoded_word(&word[..end])?, end))) else {
+            decoded.push_str(&rest[..start + 2]);
+            rest = &rest[start + 2..];
+            after_word = false;
+            continue;
+        };
+
+        // Whitespace between two encoded words is not part of the text
+        let between = &rest[..start];
+        if !(after_word && between.trim().is_empty()) {
+            decoded.push_str(between);
+        }
+        decoded.push_str(&text);
+        rest = &word[len..];
+        after_word = true;
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+/// Length of the encoded word at the start of `word`, up to and including the closing `?=`
+fn encoded_word_end(word: &str) -> Option<usize> {
+    // =?charset?encoding?text?= has three '?' before the closing "?="
+    let mut questions = word.match_indices('?').skip(2);
+    let (third, _) = questions.next()?;
+    word[third + 1..].find("?=").map(|end| third + 1 + end + 2)
+}
+
+fn decode_encoded_word(word: &str) -> Option<String> {
+    let mut parts = word.strip_prefix("=?")?.strip_suffix("?=")?.splitn(3, '?');
+    let charset = parts.next()?.split('*').next()?.to_ascii_lowercase();
+    let encoding = parts.next()?;
+    let text = parts.next()?;
+
+    let bytes = match encoding {
+        "B" | "b" => BASE64.decode(text).ok()?,
+        "Q" | "q" => decode_quoted_printable(&text.replace('_', " ")),
+        _ => return None,
+    };
+
+    match charset.as_str() {
+        "utf-8" | "utf8" | "us-ascii" => Some(String::from_utf8_lossy(&bytes).into_owned()),
+        "iso-8859-1" | "latin1" => Some(bytes.iter().map(|&b| b as char).collect()),
+        _ => None,
+    }
+}
+
+/// Render HTML as readable plain text for destinations that cannot display it
+///
+/// Tags are dropped (with line breaks for block elements), `script` and `style`
+/// contents are skipped and entities are decoded.
+pub fn html_to_text(html: &str) -> String {
+    let mut text = String::with_capacity(html.len());
+    let mut rest = html;
+
+    while let Some(start) = rest.find(['<', '&']) {
+        push_collapsed(&mut text, &rest[..start]);
+        rest = &rest[start..];
+
+        if rest.starts_with('&') {
+            let (decoded, len) = decode_entity(rest);
+            text.push_str(&decoded);
+            rest = &rest[len..];
+            continue;
+        }
+
+        if let Some(comment) = rest.strip_prefix("<!--") {
+            rest = comment.find("-->").map_or("", |end| &comment[end + 3..]);
+            continue;
+        }
+
+        let Some(end) = rest.find('>') else {
+            // Not a tag after all, keep the rest as text
+            break;
+        };
+        let tag = rest[1..end].trim();
+        rest = &rest[end + 1..];
+
+        let closing = tag.starts_with('/');
+        let name: String = tag
+            .trim_start_matches('/')
+            .chars()
+            .take_while(|c| c.is_ascii_alphanumeric())
+            .collect::<String>()
+            .to_ascii_lowercase();
+
+        match name.as_str() {
+            "script" | "style" | "head" | "title" if !closing => {
+                let close = format!("</{}", name);
+                rest = rest
+                    .to_ascii_lowercase()
+                    .find(&close)
+                    .and_then(|at| rest[at..].find('>').map(|end| &rest[at + end + 1..]))
+                    .unwrap_or("");
+            }
+            "br" => text.push('\n'),
+            "li" if !closing => {
+                push_newline(&mut text);
+                text.push_str("- ");
+            }
+            "p" | "div" | "tr" | "table" | "ul" | "ol" | "li" | "blockquote" | "pre" | "hr" | "section"
+            | "article" | "header" | "footer" | "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                push_newline(&mut text);
+            }
+            "td" | "th" if closing => text.push(' '),
+            _ => {}
+        }
+    }
+    push_collapsed(&mut text, rest);
+
+    // Tidy up: no trailing spaces and at most one blank line in a row
+    let mut result = String::with_capacity(text.len());
+    let mut blank_lines = 0;
+    for line in text.lines().map(str::trim) {
+        if line.is_empty() {
+            blank_lines += 1;
+            if blank_lines > 1 || result.is_empty() {
+                continue;
+            }
+        } else {
+            blank_lines = 0;
+        }
+        result.push_str(line);
+        result.push('\n');
+    }
+    result.trim_end().to_string()
+}
+
+/// Shorten `text` to at most `max_chars` characters, ending it with `notice` if anything was cut
+pub fn truncate(text: &str, max_chars: usize, notice: &str) -> String {
+    if text.chars().count() <= max_chars {
+        return text.to_string();
+    }
+
+    let keep = max_chars.saturating_sub(notice.chars().count());
+    let mut truncated: String = text.chars().take(keep).collect();
+    truncated.truncate(truncated.trim_end().len());
+    truncated.push_str(notice);
+    truncated
+}
+
+/// Append HTML text content, collapsing whitespace the way a browser would
+fn push_collapsed(text: &mut String, content: &str) {
+    for (i, word) in content.split_ascii_whitespace().enumerate() {
+        let needs_space = i > 0 || content.starts_with(|c: char| c.is_ascii_whitespace());
+        if needs_space && !text.is_empty() && !text.ends_with([' ', '\n']) {
+            text.push(' ');
+        }
+        text.push_str(word);
+    }
+    if content.ends_with(|c: char| c.is_ascii_whitespace()) && !text.is_empty() && !text.ends_with([' ', '\n']) {
+        text.push(' ');
+    }
+}
+
+fn push_newline(text: &mut String) {
+    text.truncate(text.trim_end_matches(' ').len());
+    if !text.is_empty() {
+        text.push('\n');
+    }
+}
+
+/// Decode the HTML entity at the start of `input`, returning it and the length consumed
+fn decode_entity(input: &str) -> (String, usize) {
+    let Some(end) = input.find(';').filter(|&end| end <= 10) else {
+        return ("&".to_string(), 1);
+    };
+    let entity = &input[1..end];
+
+    let decoded = match entity {
+        "amp" => Some('&'),
+        "lt" => Some('<'),
+        "gt" => Some('>'),
+        "quot" => Some('"'),
+        "apos" => Some('\''),
+        "nbsp" => Some(' '),
+        _ => entity
+            .strip_prefix("#x")
+            .or_else(|| entity.strip_prefix("#X"))
+            .map(|hex| u32::from_str_radix(hex, 16))
+            .or_else(|| entity.strip_prefix('#').map(str::parse))
+            .and_then(Result::ok)
+            .and_then(char::from_u32),
+    };
+
+    match decoded {
+        Some(c) => (c.to_string(), end + 1),
+        None => ("&".to_string(), 1),
+    }
+}
+
+/// Collect the text, HTML and attachments of a part, descending into nested multiparts
+fn parse_part(
+    headers: &str,
+    body: &str,
+    text_parts: &mut Vec<String>,
+    html_parts: &mut Vec<String>,
+    attachments: &mut Vec<Attachment>,
+) {
+    let content_type = get_header(headers, "content-type");
+    let ct = content_type.to_lowercase();
+
+    if ct.starts_with("multipart/") {
+        let Some(boundary) = header_param(&content_type, "boundary") else {
+            tracing::warn!("Multipart email missing boundary");
+            text_parts.push(body.to_string());
+            return;
+        };
+        let boundary = format!("--{}", boundary);
+
+        // Split by boundary, skipping the preamble and stopping at the closing delimiter
+        for part in body.split(&boundary).skip(1) {
+            if part.starts_with("--") {
+                break;
+            }
+            let part = part.strip_prefix("\r\n").or_else(|| part.strip_prefix('\n')).unwrap_or(part);
+
+            // Split part into headers and body; a part without headers is plain text
+            let (part_headers, part_body) = if part.starts_with(['\r', '\n']) {
+                ("", part.trim_start_matches(['\r', '\n']))
+            } else {
+                match split_headers_body(part) {
+                    Some(split) => split,
+                    None => continue,
+                }
+            };
+            parse_part(part_headers, part_body.trim_end(), text_parts, html_parts, attachments);
+        }
+        return;
+    }
+
+    let disposition = get_header(headers, "content-disposition").to_lowercase();
+    let content_id = Some(get_header(headers, "content-id"))
+        .map(|id| id.trim().trim_start_matches('<').trim_end_matches('>').to_string())
+        .filter(|id| !id.is_empty());
+
+    // Check if this is an attachment
+    let is_text = ct.is_empty() || ct.starts_with("text/plain") || ct.starts_with("text/html");
+    let is_attachment = disposition.starts_with("attachment")
+        || header_param(&content_type, "name").is_some()
+        || content_id.is_some();
+
+    if is_attachment || !is_text {
+        // It's an attachment or binary content
+        let filename = extract_filename(headers, &content_type).or_else(|| content_id.clone());
+        if let Some(filename) = filename {
+            attachments.push(Attachment {
+                filename,
+                content: decode_bytes(body, headers),
+                content_type: (!content_type.is_empty()).then_some(content_type),
+                inline: disposition.starts_with("inline") || (content_id.is_some() && disposition.is_empty()),
+                content_id,
+            });
+        }
+    } else if ct.starts_with("text/html") {
+        html_parts.push(decode_body(body, headers));
+    } else {
+        text_parts.push(decode_body(body, headers));
+    }
+}
+
+/// Value of a `name=value` parameter in a structured header
+fn header_param(value: &str, param: &str) -> Option<String> {
+    value.split(';').skip(1).find_map(|part| {
+        let (key, val) = part.split_once('=')?;
+        key.trim()
+            .eq_ignore_ascii_case(param)
+            .then(|| val.trim().trim_matches('"').trim_matches('\'').to_string())
+    })
+}
+
+/// Extract filename from headers
+fn extract_filename(headers: &str, content_type: &str) -> Option<String> {
+    // Try Content-Disposition first, then the Content-Type name parameter
+    header_param(&get_header(headers, "content-disposition"), "filename")
+        .or_else(|| header_param(content_type, "name"))
+}
+
+/// Decode text body based on transfer encoding
+fn decode_body(body: &str, headers: &str) -> String {
+    String::from_utf8_lossy(&decode_bytes(body, headers)).into_owned()
+}
+
+/// Decode body based on transfer encoding
+fn decode_bytes(body: &str, headers: &str) -> Vec<u8> {
+    let encoding = get_header(headers, "content-transfer-encoding").to_lowercase();
+
+    match encoding.as_str() {
+        "quoted-printable" => decode_quoted_printable(body),
+        "base64" => {
+            // Try to decode base64, fallback to raw if it fails
+            match BASE64.decode(body.replace(['\r', '\n', ' ', '\t'], "")) {
+                Ok(decoded) => decoded,
+                Err(_) => body.as_bytes().to_vec(),
+            }
+        }
+        _ => body.as_bytes().to_vec(),
+    }
+}
+
+/// Decode quoted-printable
+fn decode_quoted_printable(input: &str) -> Vec<u8> {
+    let bytes = input.as_bytes();
+    let mut result = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] != b'=' {
+            result.push(bytes[i]);
+            i += 1;
+            continue;
+        }
+
+        // Soft line break
+        if bytes[i + 1..].starts_with(b"\r\n") {
+            i += 3;
+            continue;
+        }
+        if bytes[i + 1..].starts_with(b"\n") {
+            i += 2;
+            continue;
+        }
+
+        // Decode hex
+        let decoded = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match decoded {
+            Some(byte) => {
+                result.push(byte);
+                i += 3;
+            }
+            None => {
+                result.push(b'=');
+                i += 1;
+            }
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn recipients(list: &[&str]) -> Vec<String> {
+        list.iter().map(|r| r.to_string()).collect()
+    }
+
+    fn emails(list: &[Address]) -> Vec<&str> {
+        list.iter().map(|address| address.email.as_str()).collect()
+    }
+
+    #[test]
+    fn sort_recipients_keeps_bcc_hidden() {
+        let headers = "To: \"Alice\" <alice@example.com>\r\nCc: carol@example.com\r\n";
+        let sorted = sort_recipients(
+            &recipients(&["alice@example.com", "carol@example.com", "bob@example.com"]),
+            headers,
+        );
+
+        assert_eq!(emails(&sorted.to), ["alice@example.com"]);
+        assert_eq!(sorted.to[0].name.as_deref(), Some("Alice"));
+        assert_eq!(emails(&sorted.cc), ["carol@example.com"]);
+        assert_eq!(emails(&sorted.bcc), ["bob@example.com"]);
+    }
+
+    #[test]
+    fn sort_recipients_promotes_one_bcc_recipient_for_bcc_only_mail() {
+        let headers = "To: undisclosed-recipients:;\r\n";
+        let sorted = sort_recipients(
+            &recipients(&["a@example.com", "b@example.com", "c@example.com"]),
+            headers,
+        );
+
+        assert_eq!(emails(&sorted.to), ["a@example.com"]);
+        assert!(sorted.cc.is_empty());
+        assert_eq!(emails(&sorted.bcc), ["b@example.com", "c@example.com"]);
+    }
+
+    #[test]
+    fn sort_recipients_promotes_a_cc_recipient_for_cc_only_mail() {
+        let headers = "To: list@example.org\r\nCc: a@example.com, b@example.com\r\n";
+        let sorted = sort_recipients(
+            &recipients(&["a@example.com", "b@example.com", "hidden@example.com"]),
+            headers,
+        );
+
+        assert_eq!(emails(&sorted.to), ["a@example.com"]);
+        assert_eq!(emails(&sorted.cc), ["b@example.com"]);
+        assert_eq!(emails(&sorted.bcc), ["hidden@example.com"]);
+    }
+
+    #[test]
+    fn sort_recipients_drops_duplicates() {
+        let headers = "To: a@example.com\r\nCc: a@example.com\r\n";
+        let sorted = sort_recipients(
+            &recipients(&["a@example.com", "A@Example.com", "b@example.com", "b@example.com"]),
+            headers,
+        );
+
+        assert_eq!(emails(&sorted.to), ["a@example.com"]);
+        assert!(sorted.cc.is_empty());
+        assert_eq!(emails(&sorted.bcc), ["b@example.com"]);
+    }
+
+    #[test]
+    fn decode_header_value_joins_adjacent_encoded_words() {
+        assert_eq!(decode_header_value("=?UTF-8?B?SGVsbG8s?= =?UTF-8?Q?_W=C3=B6rld?="), "Hello, Wörld");
+        assert_eq!(decode_header_value("Re: =?iso-8859-1?q?caf=E9?= menu"), "Re: café menu");
+        assert_eq!(decode_header_value("=?utf-8?q?a?=\r\n =?utf-8?q?b?= c"), "ab c");
+    }
+
+    #[test]
+    fn decode_header_value_keeps_unknown_charsets_and_broken_words() {
+        assert_eq!(decode_header_value("=?koi8-r?B?8NLJ18XU?= there"), "=?koi8-r?B?8NLJ18XU?= there");
+        assert_eq!(decode_header_value("=?UTF-8?X?abc?= =?oops"), "=?UTF-8?X?abc?= =?oops");
+        assert_eq!(decode_header_value("plain subject"), "plain subject");
+    }
+
+    #[test]
+    fn html_to_text_skips_scripts_with_mixed_case_close_tags() {
+        let html = "<p>Hi</p><SCRIPT>document.write('</b>')</Script ><style>p { }</STYLE><p>there</p>";
+
+        assert_eq!(html_to_text(html), "Hi\n\nthere");
+    }
+
+    #[test]
+    fn html_to_text_decodes_named_numeric_and_hex_entities() {
+        let html = "&lt;a&gt; &amp; &#233; &#xE9; &#X263A; &bogus; & alone";
+
+        assert_eq!(html_to_text(html), "<a> & é é ☺ &bogus; & alone");
+    }
+
+    #[test]
+    fn truncate_cuts_at_character_boundaries() {
+        assert_eq!(truncate("héllo wörld", 8, "…"), "héllo w…");
+        assert_eq!(truncate("😀😀😀😀", 3, "…"), "😀😀…");
+        assert_eq!(truncate("héllo", 5, "…"), "héllo");
+        // The notice counts towards the limit, so nothing is left of the text
+        assert_eq!(truncate("héllo", 1, "…"), "…");
+    }
+}
@@ -1,9 +1,39 @@
 pub mod webhook;
 pub mod resend;
+pub mod failover;
+pub mod smtp;
+pub mod sendgrid;
+pub mod mailgun;
+pub mod postmark;
+pub mod ses;
+pub mod msgraph;
+pub mod gmail;
+pub mod file;
+pub mod mbox;
+pub mod exec;
+pub mod slack;
+pub mod discord;
+pub mod oauth;
+pub mod mime;
+
+use std::time::Duration;
 
 use webhook::WebhookStrategy;
 use resend::ResendStrategy;
-use crate::config::StrategyConfig;
+use failover::FailoverStrategy;
+use smtp::SmtpStrategy;
+use sendgrid::SendgridStrategy;
+use mailgun::MailgunStrategy;
+use postmark::PostmarkStrategy;
+use ses::SesStrategy;
+use msgraph::MsgraphStrategy;
+use gmail::GmailStrategy;
+use file::FileStrategy;
+use mbox::MboxStrategy;
+use exec::ExecStrategy;
+use slack::SlackStrategy;
+use discord::DiscordStrategy;
+use crate::config::{RouteOverrides, StrategyConfig};
 
 /// Email data structure passed to API strategies
 #[derive(Debug, Clone)]
@@ -12,7 +42,88 @@ pub struct EmailData {
     pub to: Vec<String>,
     pub subject: String,
     pub body: String,
-    pub raw_data: String,
+    /// The message exactly as received; 8BITMIME bodies need not be valid UTF-8
+    pub raw_data: Vec<u8>,
+    /// Username the SMTP client authenticated as, if any
+    pub authenticated_user: Option<String>,
+    /// Settings of the route the recipients were sent through
+    pub overrides: RouteOverrides,
+}
+
+impl EmailData {
+    /// The raw message as text for parsing, with invalid UTF-8 replaced
+    pub fn raw_text(&self) -> std::borrow::Cow<'_, str> {
+        String::from_utf8_lossy(&self.raw_data)
+    }
+
+    /// A message from `sender@example.com` for tests, with the envelope taken from the arguments
+    #[cfg(test)]
+    pub(crate) fn for_test(to: &[&str], raw_data: &str) -> Self {
+        Self {
+            from: "sender@example.com".to_string(),
+            to: to.iter().map(|recipient| recipient.to_string()).collect(),
+            subject: "Test".to_string(),
+            body: raw_data.to_string(),
+            raw_data: raw_data.as_bytes().to_vec(),
+            authenticated_user: None,
+            overrides: RouteOverrides::default(),
+        }
+    }
+}
+
+/// Whether a failed delivery is worth retrying
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailureKind {
+    /// The provider may accept the message later (timeouts, rate limits, 5xx)
+    Transient,
+    /// The provider rejected the message and retrying will not help
+    Permanent,
+}
+
+/// Error returned by strategies that know whether a failure is permanent
+/// Errors of any other type are treated as transient
+#[derive(Debug)]
+pub struct DeliveryError {
+    pub kind: FailureKind,
+    pub message: String,
+}
+
+impl DeliveryError {
+    pub fn transient(message: impl Into<String>) -> Self {
+        Self { kind: FailureKind::Transient, message: message.into() }
+    }
+
+    pub fn permanent(message: impl Into<String>) -> Self {
+        Self { kind: FailureKind::Permanent, message: message.into() }
+    }
+
+    /// Classify an unsuccessful HTTP response: 408, 429 and 5xx are transient, other 4xx permanent
+    pub fn from_status(status: reqwest::StatusCode, message: impl Into<String>) -> Self {
+        let transient = status.is_server_error()
+            || status == reqwest::StatusCode::TOO_MANY_REQUESTS
+            || status == reqwest::StatusCode::REQUEST_TIMEOUT;
+
+        if transient {
+            Self::transient(message)
+        } else {
+            Self::permanent(message)
+        }
+    }
+}
+
+impl std::fmt::Display for DeliveryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for DeliveryError {}
+
+/// Failure kind of an error returned by [`ApiStrategy::send_email`]
+pub fn failure_kind(err: &anyhow::Error) -> FailureKind {
+    err.downcast_ref::<DeliveryError>()
+        .map(|e| e.kind)
+        .unwrap_or(FailureKind::Transient)
 }
 
 /// Enum representing all available API strategies
@@ -20,6 +131,19 @@ pub struct EmailData {
 pub enum ApiStrategy {
     Webhook(WebhookStrategy),
     Resend(ResendStrategy),
+    Failover(FailoverStrategy),
+    Smtp(SmtpStrategy),
+    Sendgrid(SendgridStrategy),
+    Mailgun(MailgunStrategy),
+    Postmark(PostmarkStrategy),
+    Ses(SesStrategy),
+    Msgraph(MsgraphStrategy),
+    Gmail(GmailStrategy),
+    File(FileStrategy),
+    Mbox(MboxStrategy),
+    Exec(ExecStrategy),
+    Slack(SlackStrategy),
+    Discord(DiscordStrategy),
 }
 
 impl ApiStrategy {
@@ -28,40 +152,127 @@ impl ApiStrategy {
         match self {
             ApiStrategy::Webhook(s) => s.send_email(email).await,
             ApiStrategy::Resend(s) => s.send_email(email).await,
+            ApiStrategy::Failover(s) => s.send_email(email).await,
+            ApiStrategy::Smtp(s) => s.send_email(email).await,
+            ApiStrategy::Sendgrid(s) => s.send_email(email).await,
+            ApiStrategy::Mailgun(s) => s.send_email(email).await,
+            ApiStrategy::Postmark(s) => s.send_email(email).await,
+            ApiStrategy::Ses(s) => s.send_email(email).await,
+            ApiStrategy::Msgraph(s) => s.send_email(email).await,
+            ApiStrategy::Gmail(s) => s.send_email(email).await,
+            ApiStrategy::File(s) => s.send_email(email).await,
+            ApiStrategy::Mbox(s) => s.send_email(email).await,
+            ApiStrategy::Exec(s) => s.send_email(email).await,
+            ApiStrategy::Slack(s) => s.send_email(email).await,
+            ApiStrategy::Discord(s) => s.send_email(email).await,
         }
     }
     
-    /// Get the name of this strategy
+    /// Get the type of this strategy, as named by `type` in the configuration
     pub fn name(&self) -> &'static str {
         match self {
             ApiStrategy::Webhook(_) => "webhook",
             ApiStrategy::Resend(_) => "resend",
+            ApiStrategy::Failover(_) => "failover",
+            ApiStrategy::Smtp(_) => "smtp",
+            ApiStrategy::Sendgrid(_) => "sendgrid",
+            ApiStrategy::Mailgun(_) => "mailgun",
+            ApiStrategy::Postmark(_) => "postmark",
+            ApiStrategy::Ses(_) => "ses",
+            ApiStrategy::Msgraph(_) => "msgraph",
+            ApiStrategy::Gmail(_) => "gmail",
+            ApiStrategy::File(_) => "file",
+            ApiStrategy::Mbox(_) => "mbox",
+            ApiStrategy::Exec(_) => "exec",
+            ApiStrategy::Slack(_) => "slack",
+            ApiStrategy::Discord(_) => "discord",
+        }
+    }
+
+    /// Get the configured id of this strategy instance
+    pub fn id(&self) -> &str {
+        match self {
+            ApiStrategy::Webhook(s) => s.id(),
+            ApiStrategy::Resend(s) => s.id(),
+            ApiStrategy::Failover(s) => s.id(),
+            ApiStrategy::Smtp(s) => s.id(),
+            ApiStrategy::Sendgrid(s) => s.id(),
+            ApiStrategy::Mailgun(s) => s.id(),
+            ApiStrategy::Postmark(s) => s.id(),
+            ApiStrategy::Ses(s) => s.id(),
+            ApiStrategy::Msgraph(s) => s.id(),
+            ApiStrategy::Gmail(s) => s.id(),
+            ApiStrategy::File(s) => s.id(),
+            ApiStrategy::Mbox(s) => s.id(),
+            ApiStrategy::Exec(s) => s.id(),
+            ApiStrategy::Slack(s) => s.id(),
+            ApiStrategy::Discord(s) => s.id(),
         }
     }
 }
 
 /// Factory function to create a strategy from configuration
 pub fn create_strategy(config: StrategyConfig) -> anyhow::Result<ApiStrategy> {
-    match config.strategy_type.as_str() {
-        "webhook" | "http" | "generic" => {
-            let url = config.api_url
-                .clone()
-                .unwrap_or_else(|| "http://localhost:3000/email".to_string());
-            Ok(ApiStrategy::Webhook(WebhookStrategy::new(url, config.extra_headers)?))
+    let id = config.id();
+
+    match config {
+        StrategyConfig::Webhook(config) => {
+            Ok(ApiStrategy::Webhook(WebhookStrategy::new(id, config)?))
+        }
+        StrategyConfig::Resend(config) => {
+            Ok(ApiStrategy::Resend(ResendStrategy::new(id, config)?))
+        }
+        StrategyConfig::Failover(config) => {
+            Ok(ApiStrategy::Failover(FailoverStrategy::new(
+                id,
+                create_strategies(config.strategies)?,
+                config.failure_threshold,
+                Duration::from_secs(config.cooldown_secs),
+            )?))
+        }
+        StrategyConfig::Smtp(config) => {
+            Ok(ApiStrategy::Smtp(SmtpStrategy::new(id, config)?))
+        }
+        StrategyConfig::Sendgrid(config) => {
+            Ok(ApiStrategy::Sendgrid(SendgridStrategy::new(id, config)?))
+        }
+        StrategyConfig::Mailgun(config) => {
+            Ok(ApiStrategy::Mailgun(MailgunStrategy::new(id, config)?))
+        }
+        StrategyConfig::Postmark(config) => {
+            Ok(ApiStrategy::Postmark(PostmarkStrategy::new(id, config)?))
+        }
+        StrategyConfig::Ses(config) => {
+            Ok(ApiStrategy::Ses(SesStrategy::new(id, config)?))
+        }
+        StrategyConfig::Msgraph(config) => {
+            Ok(ApiStrategy::Msgraph(MsgraphStrategy::new(id, config)?))
+        }
+        StrategyConfig::Gmail(config) => {
+            Ok(ApiStrategy::Gmail(GmailStrategy::new(id, config)?))
+        }
+        StrategyConfig::File(config) => {
+            Ok(ApiStrategy::File(FileStrategy::new(id, config)?))
+        }
+        StrategyConfig::Mbox(config) => {
+            Ok(ApiStrategy::Mbox(MboxStrategy::new(id, config)?))
         }
-        "resend" => {
-            let api_key = config.api_key
-                .ok_or_else(|| anyhow::anyhow!("api_key is required for resend strategy"))?;
-            Ok(ApiStrategy::Resend(ResendStrategy::new(api_key)?))
+        StrategyConfig::Exec(config) => {
+            Ok(ApiStrategy::Exec(ExecStrategy::new(id, config)?))
         }
-        _ => {
-            anyhow::bail!("Unknown API strategy: {}", config.strategy_type)
+        StrategyConfig::Slack(config) => {
+            Ok(ApiStrategy::Slack(SlackStrategy::new(id, config)?))
+        }
+        StrategyConfig::Discord(config) => {
+            Ok(ApiStrategy::Discord(DiscordStrategy::new(id, config)?))
         }
     }
 }
 
 /// Create all strategies from configuration
 pub fn create_strategies(configs: Vec<StrategyConfig>) -> anyhow::Result<Vec<ApiStrategy>> {
+    check_unique_ids(&configs)?;
+
     let mut strategies = Vec::new();
     
     for config in configs {
@@ -70,3 +281,51 @@ pub fn create_strategies(configs: Vec<StrategyConfig>) -> anyhow::Result<Vec<Api
     
     Ok(strategies)
 }
+
+/// Strategy ids have to be unique, including the members of failover groups
+fn check_unique_ids(configs: &[StrategyConfig]) -> anyhow::Result<()> {
+    fn collect(configs: &[StrategyConfig], ids: &mut Vec<String>) -> anyhow::Result<()> {
+        for config in configs {
+            let id = config.id();
+            if ids.contains(&id) {
+                anyhow::bail!("Duplicate strategy id \"{}\", give each strategy a unique \"id\"", id);
+            }
+            ids.push(id);
+            if let StrategyConfig::Failover(failover) = config {
+                collect(&failover.strategies, ids)?;
+            }
+        }
+        Ok(())
+    }
+
+    collect(configs, &mut Vec::new())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn strategy_names_match_the_configured_types() {
+        let dir = tempfile::tempdir().unwrap();
+        let configs: Vec<StrategyConfig> = [
+            json!({ "type": "webhook", "api_url": "http://127.0.0.1:9" }),
+            json!({ "type": "smtp", "host": "127.0.0.1" }),
+            json!({ "type": "sendgrid", "api_key": "key" }),
+            json!({ "type": "file", "dir": dir.path() }),
+            json!({ "type": "mbox", "path": dir.path().join("mbox") }),
+            json!({ "type": "exec", "command": ["true"] }),
+            json!({ "type": "slack", "webhook_url": "http://127.0.0.1:9" }),
+            json!({ "type": "discord", "webhook_url": "http://127.0.0.1:9" }),
+        ]
+        .into_iter()
+        .map(|config| serde_json::from_value(config).unwrap())
+        .collect();
+
+        for config in configs {
+            let type_name = config.type_name();
+            assert_eq!(create_strategy(config).unwrap().name(), type_name);
+        }
+    }
+}
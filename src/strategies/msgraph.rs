@@ -0,0 +1,326 @@
+use super::mime::{get_header, message_headers, parse_addresses, parse_email, sort_recipients, Address};
+use super::oauth::{request_token, TokenCache, TokenResponse};
+use super::{DeliveryError, EmailData};
+use crate::config::MsgraphConfig;
+
+/// Microsoft Graph `sendMail` strategy using the OAuth2 client credentials flow
+/// https://learn.microsoft.com/en-us/graph/api/user-sendmail
+#[derive(Debug, Clone)]
+pub struct MsgraphStrategy {
+    id: String,
+    client: reqwest::Client,
+    client_id: String,
+    client_secret: String,
+    user_id: Option<String>,
+    save_to_sent_items: bool,
+    token_url: String,
+    api_url: reqwest::Url,
+    token: TokenCache,
+}
+
+const GRAPH_SCOPE: &str = "https://graph.microsoft.com/.default";
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SendMailRequest {
+    message: Message,
+    save_to_sent_items: bool,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Message {
+    subject: String,
+    body: ItemBody,
+    from: Recipient,
+    to_recipients: Vec<Recipient>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    cc_recipients: Vec<Recipient>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    bcc_recipients: Vec<Recipient>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    reply_to: Vec<Recipient>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    attachments: Vec<FileAttachment>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ItemBody {
+    content_type: &'static str,
+    content: String,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Recipient {
+    email_address: EmailAddress,
+}
+
+#[derive(serde::Serialize)]
+struct EmailAddress {
+    address: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+}
+
+impl From<Address> for Recipient {
+    fn from(address: Address) -> Self {
+        Self {
+            email_address: EmailAddress { address: address.email, name: address.name },
+        }
+    }
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct FileAttachment {
+    #[serde(rename = "@odata.type")]
+    odata_type: &'static str,
+    name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    content_type: Option<String>,
+    content_bytes: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    content_id: Option<String>,
+    is_inline: bool,
+}
+
+impl MsgraphStrategy {
+    pub fn new(id: String, config: MsgraphConfig) -> anyhow::Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(std::time::Duration::from_secs(30))
+            .build()?;
+
+        let token_url = config.token_url.unwrap_or_else(|| {
+            format!("https://login.microsoftonline.com/{}/oauth2/v2.0/token", config.tenant_id)
+        });
+        let api_url = reqwest::Url::parse(&config.api_url)
+            .map_err(|err| anyhow::anyhow!("Invalid api_url for msgraph strategy {}: {}", id, err))?;
+        if api_url.cannot_be_a_base() {
+            anyhow::bail!("Invalid api_url for msgraph strategy {}: {}", id, config.api_url);
+        }
+
+        Ok(Self {
+            id,
+            client,
+            client_id: config.client_id,
+            client_secret: config.client_secret,
+            user_id: config.user_id,
+            save_to_sent_items: config.save_to_sent_items,
+            token_url,
+            api_url,
+            token: TokenCache::default(),
+        })
+    }
+
+    pub async fn send_email(&self, email: EmailData) -> anyhow::Result<()> {
+        tracing::info!("Graph strategy {} processing email from: {}", self.id, email.from);
+
+        let user = self.user_id.as_deref().unwrap_or(&email.from);
+        let mut url = self.api_url.clone();
+        url.path_segments_mut()
+            .expect("api_url is checked to be a base URL")
+            .pop_if_empty()
+            .extend(["users", user, "sendMail"]);
+
+        let request = self.build_request(&email);
+
+        // A rejected token may have been revoked early, so fetch a new one and try once more
+        let mut response = self.post(&url, &request).await?;
+        if response.status() == reqwest::StatusCode::UNAUTHORIZED {
+            self.token.invalidate().await;
+            response = self.post(&url, &request).await?;
+        }
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let text = response.text().await.unwrap_or_default();
+            return Err(DeliveryError::from_status(status, format!("Graph sendMail failed: {} - {}", status, text)).into());
+        }
+
+        tracing::info!("Graph email sent successfully via mailbox {}", user);
+        Ok(())
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    async fn post(&self, url: &reqwest::Url, request: &SendMailRequest) -> anyhow::Result<reqwest::Response> {
+        let token = self.token.get(|| self.fetch_token()).await?;
+
+        Ok(self.client
+            .post(url.clone())
+            .bearer_auth(token)
+            .json(request)
+            .send()
+            .await?)
+    }
+
+    async fn fetch_token(&self) -> anyhow::Result<TokenResponse> {
+        request_token(
+            &self.client,
+            &self.token_url,
+            &[
+                ("grant_type", "client_credentials"),
+                ("client_id", &self.client_id),
+                ("client_secret", &self.client_secret),
+                ("scope", GRAPH_SCOPE),
+            ],
+        )
+        .await
+    }
+
+    fn build_request(&self, email: &EmailData) -> SendMailRequest {
+        let raw_text = email.raw_text();
+        let headers = message_headers(&raw_text).unwrap_or_default();
+        let parsed = parse_email(&raw_text);
+        let recipients = sort_recipients(&email.to, headers);
+        let convert = |list: Vec<Address>| list.into_iter().map(Recipient::from).collect();
+
+        // Graph messages have a single body, so HTML wins over the text alternative
+        let body = match (parsed.html, parsed.text) {
+            (Some(html), _) => ItemBody { content_type: "HTML", content: html },
+            (None, text) => ItemBody { content_type: "Text", content: text.unwrap_or_default() },
+        };
+
+        let from = parse_addresses(&get_header(headers, "from"))
+            .into_iter()
+            .find(|address| address.email.eq_ignore_ascii_case(&email.from))
+            .unwrap_or_else(|| Address { name: None, email: email.from.clone() });
+
+        let attachments = parsed
+            .attachments
+            .into_iter()
+            .map(|attachment| FileAttachment {
+                odata_type: "#microsoft.graph.fileAttachment",
+                content_bytes: attachment.content_base64(),
+                content_type: attachment.mime_type().map(str::to_string),
+                name: attachment.filename,
+                content_id: attachment.content_id,
+                is_inline: attachment.inline,
+            })
+            .collect();
+
+        SendMailRequest {
+            message: Message {
+                subject: email.subject.clone(),
+                body,
+                from: from.into(),
+                to_recipients: convert(recipients.to),
+                cc_recipients: convert(recipients.cc),
+                bcc_recipients: convert(recipients.bcc),
+                reply_to: convert(parse_addresses(&get_header(headers, "reply-to"))),
+                attachments,
+            },
+            save_to_sent_items: self.save_to_sent_items,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::strategies::{failure_kind, FailureKind};
+    use serde_json::json;
+    use wiremock::matchers::{body_json, body_string_contains, header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn strategy(server: &MockServer) -> MsgraphStrategy {
+        let config = serde_json::from_value(json!({
+            "tenant_id": "tenant",
+            "client_id": "app",
+            "client_secret": "secret",
+            "token_url": format!("{}/token", server.uri()),
+            "api_url": server.uri(),
+        }))
+        .unwrap();
+        MsgraphStrategy::new("msgraph".to_string(), config).unwrap()
+    }
+
+    fn email() -> EmailData {
+        EmailData::for_test(&["a@example.com"], "Subject: Test\r\n\r\nHello\r\n")
+    }
+
+    fn token(access_token: &str) -> ResponseTemplate {
+        ResponseTemplate::new(200).set_body_json(json!({ "access_token": access_token, "expires_in": 3600 }))
+    }
+
+    #[tokio::test]
+    async fn sends_as_the_envelope_sender_and_reuses_the_token() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/token"))
+            .and(body_string_contains("grant_type=client_credentials"))
+            .and(body_string_contains("client_id=app"))
+            .and(body_string_contains("client_secret=secret"))
+            .respond_with(token("t1"))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/users/sender@example.com/sendMail"))
+            .and(header("authorization", "Bearer t1"))
+            .and(body_json(json!({
+                "message": {
+                    "subject": "Test",
+                    "body": { "contentType": "Text", "content": "Hello\r\n" },
+                    "from": { "emailAddress": { "address": "sender@example.com" } },
+                    "toRecipients": [{ "emailAddress": { "address": "a@example.com" } }],
+                },
+                "saveToSentItems": false,
+            })))
+            .respond_with(ResponseTemplate::new(202))
+            .expect(2)
+            .mount(&server)
+            .await;
+
+        let strategy = strategy(&server);
+        strategy.send_email(email()).await.unwrap();
+        strategy.send_email(email()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn rejected_tokens_are_replaced_once() {
+        let server = MockServer::start().await;
+        Mock::given(path("/token")).respond_with(token("revoked")).up_to_n_times(1).mount(&server).await;
+        Mock::given(path("/token")).respond_with(token("fresh")).expect(1).mount(&server).await;
+        Mock::given(header("authorization", "Bearer revoked"))
+            .respond_with(ResponseTemplate::new(401))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(header("authorization", "Bearer fresh"))
+            .respond_with(ResponseTemplate::new(202))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        strategy(&server).send_email(email()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn errors_are_classified_by_status() {
+        let server = MockServer::start().await;
+
+        for (status, kind) in [(400, FailureKind::Permanent), (403, FailureKind::Permanent), (503, FailureKind::Transient)] {
+            server.reset().await;
+            Mock::given(path("/token")).respond_with(token("t1")).mount(&server).await;
+            Mock::given(method("POST"))
+                .and(path("/users/sender@example.com/sendMail"))
+                .respond_with(ResponseTemplate::new(status))
+                .mount(&server)
+                .await;
+
+            let err = strategy(&server).send_email(email()).await.unwrap_err();
+            assert_eq!(failure_kind(&err), kind, "status {}", status);
+        }
+
+        // Bad credentials will not fix themselves
+        server.reset().await;
+        Mock::given(path("/token")).respond_with(ResponseTemplate::new(400)).mount(&server).await;
+        let err = strategy(&server).send_email(email()).await.unwrap_err();
+        assert_eq!(failure_kind(&err), FailureKind::Permanent);
+    }
+}
@@ -0,0 +1,83 @@
+//! OAuth2 access tokens for strategies that authenticate with short-lived bearer tokens
+
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use super::DeliveryError;
+
+/// Tokens are refreshed this long before they expire, so a token never runs out mid-request
+const EXPIRY_MARGIN: Duration = Duration::from_secs(60);
+
+/// Lifetime assumed when the token endpoint does not return `expires_in`
+const DEFAULT_LIFETIME: Duration = Duration::from_secs(300);
+
+/// Access token cache shared between clones of a strategy
+#[derive(Debug, Clone, Default)]
+pub struct TokenCache {
+    token: Arc<tokio::sync::Mutex<Option<CachedToken>>>,
+}
+
+#[derive(Debug)]
+struct CachedToken {
+    access_token: String,
+    expires_at: Instant,
+}
+
+/// Successful response of an OAuth2 token endpoint
+#[derive(Debug, serde::Deserialize)]
+pub struct TokenResponse {
+    pub access_token: String,
+    #[serde(default)]
+    pub expires_in: Option<u64>,
+}
+
+impl TokenCache {
+    /// Return the cached token, or fetch a new one if there is none or it is about to expire
+    ///
+    /// Concurrent deliveries wait for a single refresh instead of each requesting a token.
+    pub async fn get<F, Fut>(&self, fetch: F) -> anyhow::Result<String>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = anyhow::Result<TokenResponse>>,
+    {
+        let mut token = self.token.lock().await;
+
+        if let Some(cached) = token.as_ref() {
+            if cached.expires_at > Instant::now() + EXPIRY_MARGIN {
+                return Ok(cached.access_token.clone());
+            }
+        }
+
+        let response = fetch().await?;
+        let lifetime = response.expires_in.map(Duration::from_secs).unwrap_or(DEFAULT_LIFETIME);
+        *token = Some(CachedToken {
+            access_token: response.access_token.clone(),
+            expires_at: Instant::now() + lifetime,
+        });
+
+        Ok(response.access_token)
+    }
+
+    /// Drop the cached token, e.g. after the API rejected it
+    pub async fn invalidate(&self) {
+        *self.token.lock().await = None;
+    }
+}
+
+/// Request a token from an OAuth2 token endpoint with a form encoded grant
+pub async fn request_token(
+    client: &reqwest::Client,
+    url: &str,
+    form: &[(&str, &str)],
+) -> anyhow::Result<TokenResponse> {
+    let response = client.post(url).form(form).send().await?;
+
+    if !response.status().is_success() {
+        let status = response.status();
+        let text = response.text().await.unwrap_or_default();
+        return Err(DeliveryError::from_status(status, format!("OAuth token request failed: {} - {}", status, text)).into());
+    }
+
+    Ok(response.json().await?)
+}
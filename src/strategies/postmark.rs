@@ -0,0 +1,266 @@
+use super::mime::{get_header, message_headers, parse_addresses, parse_email, sort_recipients, Address};
+use super::{DeliveryError, EmailData};
+use crate::config::PostmarkConfig;
+use reqwest::header::{HeaderMap, HeaderValue};
+
+/// Postmark strategy
+/// https://postmarkapp.com/developer/api/email-api
+#[derive(Debug, Clone)]
+pub struct PostmarkStrategy {
+    id: String,
+    client: reqwest::Client,
+    message_stream: Option<String>,
+    api_url: String,
+}
+
+/// Postmark error codes worth retrying; every other code means the request itself is wrong
+/// https://postmarkapp.com/developer/api/overview#error-codes
+const RETRYABLE_ERROR_CODES: &[i64] = &[
+    100, // Maintenance
+    405, // Not allowed to send, e.g. out of credits
+    429, // Rate limit exceeded
+];
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct PostmarkPayload {
+    from: String,
+    to: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cc: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bcc: Option<String>,
+    subject: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    text_body: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    html_body: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reply_to: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    attachments: Vec<Attachment>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message_stream: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct Attachment {
+    name: String,
+    content: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    content_type: Option<String>,
+    #[serde(rename = "ContentID", skip_serializing_if = "Option::is_none")]
+    content_id: Option<String>,
+}
+
+#[derive(serde::Deserialize, Default)]
+#[serde(rename_all = "PascalCase")]
+struct PostmarkResponse {
+    #[serde(default)]
+    error_code: i64,
+    #[serde(default)]
+    message: String,
+    #[serde(rename = "MessageID", default)]
+    message_id: Option<String>,
+}
+
+impl PostmarkStrategy {
+    pub fn new(id: String, config: PostmarkConfig) -> anyhow::Result<Self> {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            reqwest::header::ACCEPT,
+            HeaderValue::from_static("application/json"),
+        );
+        headers.insert(
+            reqwest::header::CONTENT_TYPE,
+            HeaderValue::from_static("application/json"),
+        );
+        headers.insert(
+            "X-Postmark-Server-Token",
+            HeaderValue::from_str(&config.server_token)?,
+        );
+
+        let client = reqwest::Client::builder()
+            .timeout(std::time::Duration::from_secs(30))
+            .default_headers(headers)
+            .build()?;
+
+        Ok(Self {
+            id,
+            client,
+            message_stream: config.message_stream,
+            api_url: config.api_url.trim_end_matches('/').to_string(),
+        })
+    }
+
+    pub async fn send_email(&self, email: EmailData) -> anyhow::Result<()> {
+        tracing::info!("Postmark strategy {} processing email from: {}", self.id, email.from);
+
+        let raw_text = email.raw_text().into_owned();
+        let headers = message_headers(&raw_text).unwrap_or_default();
+        let parsed = parse_email(&raw_text);
+        let recipients = sort_recipients(&email.to, headers);
+
+        // The message can pick its stream, e.g. to send newsletters through a broadcast stream
+        let message_stream = Some(get_header(headers, "x-pm-message-stream"))
+            .filter(|stream| !stream.is_empty())
+            .or_else(|| self.message_stream.clone());
+
+        let from = parse_addresses(&get_header(headers, "from"))
+            .into_iter()
+            .find(|address| address.email.eq_ignore_ascii_case(&email.from))
+            .unwrap_or_else(|| Address { name: None, email: email.from.clone() });
+
+        let attachments = parsed
+            .attachments
+            .into_iter()
+            .map(|attachment| Attachment {
+                content: attachment.content_base64(),
+                content_type: attachment.mime_type().map(str::to_string),
+                name: attachment.filename,
+                content_id: attachment.content_id.map(|id| format!("cid:{}", id)),
+            })
+            .collect();
+
+        let payload = PostmarkPayload {
+            from: format_address(&from),
+            to: format_list(&recipients.to).unwrap_or_default(),
+            cc: format_list(&recipients.cc),
+            bcc: format_list(&recipients.bcc),
+            subject: email.subject,
+            text_body: parsed.text,
+            html_body: parsed.html,
+            reply_to: Some(get_header(headers, "reply-to")).filter(|reply_to| !reply_to.is_empty()),
+            attachments,
+            message_stream,
+        };
+
+        let response = self
+            .client
+            .post(format!("{}/email", self.api_url))
+            .json(&payload)
+            .send()
+            .await?;
+
+        let status = response.status();
+        let text = response.text().await.unwrap_or_default();
+        let postmark_response: PostmarkResponse = serde_json::from_str(&text).unwrap_or_default();
+
+        if !status.is_success() || postmark_response.error_code != 0 {
+            let message = format!(
+                "Postmark API request failed: {} - ErrorCode {}: {}",
+                status,
+                postmark_response.error_code,
+                if postmark_response.message.is_empty() { &text } else { &postmark_response.message }
+            );
+            let error = if RETRYABLE_ERROR_CODES.contains(&postmark_response.error_code) {
+                DeliveryError::transient(message)
+            } else if postmark_response.error_code != 0 && status.is_client_error() {
+                DeliveryError::permanent(message)
+            } else {
+                DeliveryError::from_status(status, message)
+            };
+            return Err(error.into());
+        }
+
+        tracing::info!(
+            "Postmark email sent successfully. ID: {}",
+            postmark_response.message_id.as_deref().unwrap_or("unknown")
+        );
+
+        Ok(())
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+}
+
+fn format_address(address: &Address) -> String {
+    match &address.name {
+        Some(name) => format!("\"{}\" <{}>", name.replace('"', "'"), address.email),
+        None => address.email.clone(),
+    }
+}
+
+fn format_list(addresses: &[Address]) -> Option<String> {
+    if addresses.is_empty() {
+        return None;
+    }
+    Some(addresses.iter().map(format_address).collect::<Vec<_>>().join(", "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::strategies::{failure_kind, FailureKind};
+    use serde_json::json;
+    use wiremock::matchers::{body_json, header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn strategy(server: &MockServer) -> PostmarkStrategy {
+        let config = serde_json::from_value(json!({
+            "server_token": "token-123",
+            "message_stream": "outbound",
+            "api_url": server.uri(),
+        }))
+        .unwrap();
+        PostmarkStrategy::new("postmark".to_string(), config).unwrap()
+    }
+
+    fn email() -> EmailData {
+        EmailData::for_test(&["a@example.com"], "Subject: Test\r\n\r\nHello\r\n")
+    }
+
+    async fn respond(server: &MockServer, status: u16, error_code: i64) {
+        server.reset().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(status).set_body_json(json!({ "ErrorCode": error_code, "Message": "error" })))
+            .mount(server)
+            .await;
+    }
+
+    #[tokio::test]
+    async fn posts_the_message_to_the_header_stream() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/email"))
+            .and(header("x-postmark-server-token", "token-123"))
+            .and(body_json(json!({
+                "From": "\"Sender\" <sender@example.com>",
+                "To": "\"Alice\" <a@example.com>",
+                "Bcc": "hidden@example.com",
+                "Subject": "Test",
+                "TextBody": "Hello\r\n",
+                "MessageStream": "broadcast",
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "ErrorCode": 0, "MessageID": "abc" })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let email = EmailData::for_test(
+            &["a@example.com", "hidden@example.com"],
+            "From: Sender <sender@example.com>\r\nTo: Alice <a@example.com>\r\n\
+             X-PM-Message-Stream: broadcast\r\nSubject: Test\r\n\r\nHello\r\n",
+        );
+        strategy(&server).send_email(email).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn errors_are_classified_by_error_code_and_status() {
+        let server = MockServer::start().await;
+
+        // Inactive recipient
+        respond(&server, 422, 406).await;
+        assert_eq!(failure_kind(&strategy(&server).send_email(email()).await.unwrap_err()), FailureKind::Permanent);
+
+        // Maintenance
+        respond(&server, 422, 100).await;
+        assert_eq!(failure_kind(&strategy(&server).send_email(email()).await.unwrap_err()), FailureKind::Transient);
+
+        respond(&server, 503, 0).await;
+        assert_eq!(failure_kind(&strategy(&server).send_email(email()).await.unwrap_err()), FailureKind::Transient);
+    }
+}
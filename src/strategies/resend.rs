@@ -1,14 +1,15 @@
-use super::EmailData;
-use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use super::mime::parse_email;
+use super::{DeliveryError, EmailData};
+use crate::config::ResendConfig;
 use reqwest::header::{HeaderMap, HeaderValue};
 
 /// Resend API strategy for sending emails via Resend
 /// https://resend.com/docs/api-reference/emails/send-email
 #[derive(Debug, Clone)]
 pub struct ResendStrategy {
+    id: String,
     client: reqwest::Client,
-    #[allow(dead_code)]
-    api_key: String,
+    api_url: String,
 }
 
 #[derive(serde::Serialize)]
@@ -36,7 +37,8 @@ struct Attachment {
 }
 
 impl ResendStrategy {
-    pub fn new(api_key: String) -> anyhow::Result<Self> {
+    pub fn new(id: String, config: ResendConfig) -> anyhow::Result<Self> {
+        let api_key = config.api_key;
         let mut headers = HeaderMap::new();
         headers.insert(
             reqwest::header::CONTENT_TYPE,
@@ -53,22 +55,33 @@ impl ResendStrategy {
             .build()?;
 
         Ok(Self {
+            id,
             client,
-            api_key
+            api_url: config.api_url.trim_end_matches('/').to_string(),
         })
     }
 
     pub async fn send_email(&self, email: EmailData) -> anyhow::Result<()> {
-        tracing::info!("Resend strategy processing email from: {}", email.from);
+        tracing::info!("Resend strategy {} processing email from: {}", self.id, email.from);
 
         // Parse email content
-        let (text, html, attachments) = parse_email(&email.raw_data);
+        let parsed = parse_email(&email.raw_text());
+        let (text, html) = (parsed.text, parsed.html);
+        let attachments: Vec<Attachment> = parsed
+            .attachments
+            .into_iter()
+            .map(|attachment| Attachment {
+                content: attachment.content_base64(),
+                filename: attachment.filename,
+                content_type: attachment.content_type,
+            })
+            .collect();
 
         tracing::info!(
             "Parsed email - Text: {}, HTML: {}, Attachments: {}",
             if text.is_some() { "yes" } else { "no" },
             if html.is_some() { "yes" } else { "no" },
-            attachments.as_ref().map(|a| a.len()).unwrap_or(0)
+            attachments.len()
         );
 
         let payload = ResendPayload {
@@ -78,12 +91,12 @@ impl ResendStrategy {
             text,
             html,
             reply_to: Some(email.from),
-            attachments,
+            attachments: (!attachments.is_empty()).then_some(attachments),
         };
 
         let response = self
             .client
-            .post("https://api.resend.com/emails")
+            .post(format!("{}/emails", self.api_url))
             .json(&payload)
             .send()
             .await?;
@@ -91,10 +104,11 @@ impl ResendStrategy {
         if !response.status().is_success() {
             let status = response.status();
             let text = response.text().await.unwrap_or_default();
-            anyhow::bail!("Resend API request failed: {} - {}", status, text);
+            return Err(DeliveryError::from_status(status, format!("Resend API request failed: {} - {}", status, text)).into());
         }
 
-        let resend_response: serde_json::Value = response.json().await?;
+        let text = response.text().await.unwrap_or_default();
+        let resend_response: serde_json::Value = serde_json::from_str(&text).unwrap_or_default();
         tracing::info!(
             "Resend email sent successfully. ID: {}",
             resend_response
@@ -106,206 +120,53 @@ impl ResendStrategy {
         Ok(())
     }
 
-    #[allow(dead_code)]
-    pub fn name(&self) -> &'static str {
-        "resend"
+    pub fn id(&self) -> &str {
+        &self.id
     }
 }
 
-/// Parse email and extract content
-/// Returns (text, html, attachments)
-fn parse_email(raw_data: &str) -> (Option<String>, Option<String>, Option<Vec<Attachment>>) {
-    // Split headers from body
-    let (headers, body) = match split_headers_body(raw_data) {
-        Some((h, b)) => (h, b),
-        None => return (Some(raw_data.to_string()), None, None),
-    };
-
-    // Get content type
-    let content_type = get_header(&headers, "content-type").to_lowercase();
-    let is_multipart = content_type.starts_with("multipart/");
-
-    if !is_multipart {
-        // Simple email - use body as-is
-        let is_html = content_type.contains("text/html");
-        let decoded_body = decode_body(body, &headers);
-        
-        if is_html {
-            return (None, Some(decoded_body), None);
-        } else {
-            return (Some(decoded_body), None, None);
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::strategies::{failure_kind, FailureKind};
+    use serde_json::json;
+    use wiremock::matchers::{header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn strategy(server: &MockServer) -> ResendStrategy {
+        let config = serde_json::from_value(json!({ "api_key": "re_123", "api_url": server.uri() })).unwrap();
+        ResendStrategy::new("resend".to_string(), config).unwrap()
     }
 
-    // Multipart email - parse parts
-    parse_multipart(body, &headers)
-}
-
-/// Split email into headers and body
-fn split_headers_body(raw_data: &str) -> Option<(&str, &str)> {
-    if let Some(pos) = raw_data.find("\r\n\r\n") {
-        return Some((&raw_data[..pos], &raw_data[pos + 4..]));
+    fn email() -> EmailData {
+        EmailData::for_test(&["a@example.com"], "Subject: Test\r\n\r\nHello\r\n")
     }
-    if let Some(pos) = raw_data.find("\n\n") {
-        return Some((&raw_data[..pos], &raw_data[pos + 2..]));
-    }
-    None
-}
 
-/// Get header value (case-insensitive)
-fn get_header(headers: &str, name: &str) -> String {
-    let name_lower = name.to_lowercase();
-    headers
-        .lines()
-        .find(|line| line.to_lowercase().starts_with(&format!("{}:", name_lower)))
-        .map(|line| line.splitn(2, ':').nth(1).unwrap_or("").trim().to_string())
-        .unwrap_or_default()
-}
-
-/// Decode body based on transfer encoding
-fn decode_body(body: &str, headers: &str) -> String {
-    let encoding = get_header(headers, "content-transfer-encoding").to_lowercase();
-    
-    match encoding.as_str() {
-        "quoted-printable" => decode_quoted_printable(body),
-        "base64" => {
-            // Try to decode base64, fallback to raw if it fails
-            match BASE64.decode(body.replace(['\r', '\n'], "")) {
-                Ok(decoded) => String::from_utf8_lossy(&decoded).to_string(),
-                Err(_) => body.to_string(),
-            }
-        }
-        _ => body.to_string(),
+    #[tokio::test]
+    async fn success_without_a_json_body_is_delivered() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/emails"))
+            .and(header("authorization", "Bearer re_123"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        strategy(&server).send_email(email()).await.unwrap();
     }
-}
-
-/// Parse multipart email into parts
-fn parse_multipart(body: &str, headers: &str) -> (Option<String>, Option<String>, Option<Vec<Attachment>>) {
-    // Get boundary
-    let boundary = get_header(headers, "content-type")
-        .split("boundary=")
-        .nth(1)
-        .map(|b| b.trim().trim_matches('"').trim_matches('\''))
-        .map(|b| format!("--{}", b));
-
-    let Some(boundary) = boundary else {
-        tracing::warn!("Multipart email missing boundary");
-        return (Some(body.to_string()), None, None);
-    };
 
-    let mut text_parts = Vec::new();
-    let mut html_parts = Vec::new();
-    let mut attachments = Vec::new();
-
-    // Split by boundary
-    for part in body.split(&boundary) {
-        let part = part.trim();
-        if part.is_empty() || part == "--" {
-            continue;
+    #[tokio::test]
+    async fn errors_are_classified_by_status() {
+        let server = MockServer::start().await;
+        for (status, kind) in [(422, FailureKind::Permanent), (429, FailureKind::Transient), (500, FailureKind::Transient)] {
+            server.reset().await;
+            Mock::given(method("POST"))
+                .respond_with(ResponseTemplate::new(status))
+                .mount(&server)
+                .await;
+            let error = strategy(&server).send_email(email()).await.unwrap_err();
+            assert_eq!(failure_kind(&error), kind, "status {}", status);
         }
-
-        // Split part into headers and body
-        let Some((part_headers, part_body)) = split_headers_body(part) else {
-            continue;
-        };
-
-        let part_ct = get_header(part_headers, "content-type").to_lowercase();
-        let decoded = decode_body(part_body, part_headers);
-
-        // Check if this is an attachment
-        let is_attachment = part_headers.to_lowercase().contains("content-disposition: attachment")
-            || part_ct.contains("name=");
-
-        if is_attachment || (!part_ct.contains("text/plain") && !part_ct.contains("text/html")) {
-            // It's an attachment or binary content
-            if let Some(filename) = extract_filename(part_headers, &part_ct) {
-                let content = BASE64.encode(&decoded);
-                attachments.push(Attachment {
-                    filename,
-                    content,
-                    content_type: Some(get_header(part_headers, "content-type")),
-                });
-            }
-        } else if part_ct.contains("text/html") {
-            html_parts.push(decoded);
-        } else if part_ct.contains("text/plain") {
-            text_parts.push(decoded);
-        }
-    }
-
-    let text = if text_parts.is_empty() {
-        None
-    } else {
-        Some(text_parts.join("\n\n"))
-    };
-
-    let html = if html_parts.is_empty() {
-        None
-    } else {
-        Some(html_parts.join("<br><br>"))
-    };
-
-    let attachments = if attachments.is_empty() {
-        None
-    } else {
-        Some(attachments)
-    };
-
-    (text, html, attachments)
-}
-
-/// Extract filename from headers
-fn extract_filename(headers: &str, content_type: &str) -> Option<String> {
-    // Try Content-Disposition first
-    let cd = get_header(headers, "content-disposition");
-    if let Some(filename) = cd.split("filename=").nth(1) {
-        return Some(filename.trim().trim_matches('"').trim_matches('\'').to_string());
-    }
-
-    // Try Content-Type name parameter
-    if let Some(name) = content_type.split("name=").nth(1) {
-        return Some(name.trim().trim_matches('"').trim_matches('\'').to_string());
     }
-
-    None
-}
-
-/// Decode quoted-printable
-fn decode_quoted_printable(input: &str) -> String {
-    let mut result = String::with_capacity(input.len());
-    let mut chars = input.chars().peekable();
-
-    while let Some(ch) = chars.next() {
-        if ch == '=' {
-            // Check for soft line break
-            if chars.peek() == Some(&'\r') {
-                chars.next();
-                if chars.peek() == Some(&'\n') {
-                    chars.next();
-                    continue;
-                }
-            } else if chars.peek() == Some(&'\n') {
-                chars.next();
-                continue;
-            }
-
-            // Decode hex
-            let hex: String = chars.by_ref().take(2).collect();
-            if hex.len() == 2 {
-                if let Ok(byte) = u8::from_str_radix(&hex, 16) {
-                    result.push(byte as char);
-                } else {
-                    result.push('=');
-                    result.push_str(&hex);
-                }
-            } else {
-                result.push('=');
-                result.push_str(&hex);
-            }
-        } else {
-            result.push(ch);
-        }
-    }
-
-    result
 }
@@ -0,0 +1,241 @@
+use super::mime::{get_header, message_headers, parse_addresses, parse_email, sort_recipients, Address};
+use super::{DeliveryError, EmailData};
+use crate::config::SendgridConfig;
+use reqwest::header::{HeaderMap, HeaderValue};
+
+/// SendGrid v3 Mail Send strategy
+/// https://www.twilio.com/docs/sendgrid/api-reference/mail-send/mail-send
+#[derive(Debug, Clone)]
+pub struct SendgridStrategy {
+    id: String,
+    client: reqwest::Client,
+    api_url: String,
+}
+
+#[derive(serde::Serialize)]
+struct SendgridPayload {
+    personalizations: Vec<Personalization>,
+    from: EmailAddress,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reply_to: Option<EmailAddress>,
+    subject: String,
+    content: Vec<Content>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    attachments: Vec<Attachment>,
+}
+
+#[derive(serde::Serialize)]
+struct Personalization {
+    to: Vec<EmailAddress>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    cc: Vec<EmailAddress>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    bcc: Vec<EmailAddress>,
+}
+
+#[derive(serde::Serialize)]
+struct EmailAddress {
+    email: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+}
+
+impl From<Address> for EmailAddress {
+    fn from(address: Address) -> Self {
+        Self { email: address.email, name: address.name }
+    }
+}
+
+#[derive(serde::Serialize)]
+struct Content {
+    #[serde(rename = "type")]
+    content_type: &'static str,
+    value: String,
+}
+
+#[derive(serde::Serialize)]
+struct Attachment {
+    content: String,
+    filename: String,
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    content_type: Option<String>,
+    disposition: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    content_id: Option<String>,
+}
+
+impl SendgridStrategy {
+    pub fn new(id: String, config: SendgridConfig) -> anyhow::Result<Self> {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            reqwest::header::CONTENT_TYPE,
+            HeaderValue::from_static("application/json"),
+        );
+        headers.insert(
+            reqwest::header::AUTHORIZATION,
+            HeaderValue::from_str(&format!("Bearer {}", config.api_key))?,
+        );
+
+        let client = reqwest::Client::builder()
+            .timeout(std::time::Duration::from_secs(30))
+            .default_headers(headers)
+            .build()?;
+
+        Ok(Self {
+            id,
+            client,
+            api_url: config.api_url.trim_end_matches('/').to_string(),
+        })
+    }
+
+    pub async fn send_email(&self, email: EmailData) -> anyhow::Result<()> {
+        tracing::info!("SendGrid strategy {} processing email from: {}", self.id, email.from);
+
+        let raw_text = email.raw_text().into_owned();
+        let headers = message_headers(&raw_text).unwrap_or_default();
+        let parsed = parse_email(&raw_text);
+
+        // text/plain has to come before text/html, and SendGrid refuses empty values
+        let mut content = Vec::new();
+        if let Some(text) = parsed.text.filter(|text| !text.trim().is_empty()) {
+            content.push(Content { content_type: "text/plain", value: text });
+        }
+        if let Some(html) = parsed.html.filter(|html| !html.trim().is_empty()) {
+            content.push(Content { content_type: "text/html", value: html });
+        }
+        if content.is_empty() {
+            content.push(Content { content_type: "text/plain", value: " ".to_string() });
+        }
+
+        let attachments: Vec<Attachment> = parsed
+            .attachments
+            .into_iter()
+            .map(|attachment| Attachment {
+                content: attachment.content_base64(),
+                content_type: attachment.mime_type().map(str::to_string),
+                disposition: if attachment.inline { "inline" } else { "attachment" },
+                filename: attachment.filename,
+                content_id: attachment.content_id,
+            })
+            .collect();
+
+        // Keep the display name from the From header when it is the envelope sender
+        let from_name = parse_addresses(&get_header(headers, "from"))
+            .into_iter()
+            .find(|address| address.email.eq_ignore_ascii_case(&email.from))
+            .and_then(|address| address.name);
+
+        let payload = SendgridPayload {
+            personalizations: vec![personalization(&email.to, headers)],
+            from: EmailAddress { email: email.from, name: from_name },
+            reply_to: parse_addresses(&get_header(headers, "reply-to"))
+                .into_iter()
+                .next()
+                .map(EmailAddress::from),
+            subject: email.subject,
+            content,
+            attachments,
+        };
+
+        let response = self
+            .client
+            .post(format!("{}/v3/mail/send", self.api_url))
+            .json(&payload)
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let text = response.text().await.unwrap_or_default();
+            return Err(DeliveryError::from_status(status, format!("SendGrid API request failed: {} - {}", status, text)).into());
+        }
+
+        tracing::info!(
+            "SendGrid email accepted. Message ID: {}",
+            response
+                .headers()
+                .get("x-message-id")
+                .and_then(|v| v.to_str().ok())
+                .unwrap_or("unknown")
+        );
+
+        Ok(())
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+}
+
+/// Bcc recipients go into `bcc` so SendGrid does not reveal them in the To header it generates
+fn personalization(recipients: &[String], headers: &str) -> Personalization {
+    let recipients = sort_recipients(recipients, headers);
+    let convert = |list: Vec<Address>| list.into_iter().map(EmailAddress::from).collect();
+
+    Personalization {
+        to: convert(recipients.to),
+        cc: convert(recipients.cc),
+        bcc: convert(recipients.bcc),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::strategies::{failure_kind, FailureKind};
+    use serde_json::json;
+    use wiremock::matchers::{body_json, header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn strategy(server: &MockServer) -> SendgridStrategy {
+        let config = serde_json::from_value(json!({ "api_key": "SG.key", "api_url": server.uri() })).unwrap();
+        SendgridStrategy::new("sendgrid".to_string(), config).unwrap()
+    }
+
+    #[tokio::test]
+    async fn posts_the_message_with_bcc_recipients_kept_hidden() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v3/mail/send"))
+            .and(header("authorization", "Bearer SG.key"))
+            .and(body_json(json!({
+                "personalizations": [{
+                    "to": [{ "email": "a@example.com", "name": "Alice" }],
+                    "cc": [{ "email": "c@example.com" }],
+                    "bcc": [{ "email": "hidden@example.com" }],
+                }],
+                "from": { "email": "sender@example.com", "name": "Sender" },
+                "reply_to": { "email": "replies@example.com" },
+                "subject": "Test",
+                "content": [{ "type": "text/plain", "value": "Hello\r\n" }],
+            })))
+            .respond_with(ResponseTemplate::new(202).insert_header("x-message-id", "abc"))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let email = EmailData::for_test(
+            &["a@example.com", "c@example.com", "hidden@example.com"],
+            "From: Sender <sender@example.com>\r\nTo: Alice <a@example.com>\r\nCc: c@example.com\r\n\
+             Reply-To: replies@example.com\r\nSubject: Test\r\n\r\nHello\r\n",
+        );
+        strategy(&server).send_email(email).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn errors_are_classified_by_status() {
+        let server = MockServer::start().await;
+        let email = || EmailData::for_test(&["a@example.com"], "Subject: Test\r\n\r\nHello\r\n");
+
+        for (status, kind) in [(400, FailureKind::Permanent), (429, FailureKind::Transient), (503, FailureKind::Transient)] {
+            server.reset().await;
+            Mock::given(method("POST"))
+                .respond_with(ResponseTemplate::new(status))
+                .mount(&server)
+                .await;
+
+            let err = strategy(&server).send_email(email()).await.unwrap_err();
+            assert_eq!(failure_kind(&err), kind, "status {}", status);
+        }
+    }
+}
@@ -0,0 +1,345 @@
+use super::{DeliveryError, EmailData};
+use crate::config::SesConfig;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+
+/// Amazon SES v2 `SendEmail` strategy sending the original MIME message as `Content.Raw`
+/// https://docs.aws.amazon.com/ses/latest/APIReference-V2/API_SendEmail.html
+#[derive(Debug, Clone)]
+pub struct SesStrategy {
+    id: String,
+    client: reqwest::Client,
+    url: reqwest::Url,
+    region: String,
+    credentials: Credentials,
+    configuration_set: Option<String>,
+}
+
+#[derive(Clone)]
+struct Credentials {
+    access_key_id: String,
+    secret_access_key: String,
+    session_token: Option<String>,
+}
+
+impl std::fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Credentials")
+            .field("access_key_id", &self.access_key_id)
+            .finish_non_exhaustive()
+    }
+}
+
+/// SES errors worth retrying; other 4xx errors mean the message or account needs fixing
+const RETRYABLE_ERRORS: &[&str] = &[
+    "TooManyRequestsException",
+    "LimitExceededException",
+    "SendingPausedException",
+];
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct SendEmailRequest {
+    from_email_address: String,
+    destination: Destination,
+    content: Content,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    configuration_set_name: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct Destination {
+    to_addresses: Vec<String>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct Content {
+    raw: RawMessage,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct RawMessage {
+    data: String,
+}
+
+impl SesStrategy {
+    pub fn new(id: String, config: SesConfig) -> anyhow::Result<Self> {
+        let env = |name: &str| std::env::var(name).ok().filter(|value| !value.is_empty());
+
+        let region = config.region
+            .or_else(|| env("AWS_REGION"))
+            .or_else(|| env("AWS_DEFAULT_REGION"))
+            .ok_or_else(|| anyhow::anyhow!("SES strategy {} needs a region (\"region\" or AWS_REGION)", id))?;
+
+        let credentials = match (config.access_key_id, config.secret_access_key) {
+            (Some(access_key_id), Some(secret_access_key)) => Credentials {
+                access_key_id,
+                secret_access_key,
+                session_token: config.session_token,
+            },
+            (None, None) => Credentials {
+                access_key_id: env("AWS_ACCESS_KEY_ID").ok_or_else(|| {
+                    anyhow::anyhow!("SES strategy {} needs \"access_key_id\" or AWS_ACCESS_KEY_ID", id)
+                })?,
+                secret_access_key: env("AWS_SECRET_ACCESS_KEY").ok_or_else(|| {
+                    anyhow::anyhow!("SES strategy {} needs \"secret_access_key\" or AWS_SECRET_ACCESS_KEY", id)
+                })?,
+                session_token: config.session_token.or_else(|| env("AWS_SESSION_TOKEN")),
+            },
+            _ => anyhow::bail!("SES strategy {} needs both access_key_id and secret_access_key", id),
+        };
+
+        let api_url = config
+            .api_url
+            .unwrap_or_else(|| format!("https://email.{}.amazonaws.com", region));
+        let url = reqwest::Url::parse(&format!("{}/v2/email/outbound-emails", api_url.trim_end_matches('/')))
+            .map_err(|err| anyhow::anyhow!("Invalid api_url for SES strategy {}: {}", id, err))?;
+
+        let client = reqwest::Client::builder()
+            .timeout(std::time::Duration::from_secs(30))
+            .build()?;
+
+        Ok(Self {
+            id,
+            client,
+            url,
+            region,
+            credentials,
+            configuration_set: config.configuration_set,
+        })
+    }
+
+    pub async fn send_email(&self, email: EmailData) -> anyhow::Result<()> {
+        tracing::info!("SES strategy {} processing email from: {}", self.id, email.from);
+
+        // With raw content the destination only sets the envelope, headers are left as they are
+        let request = SendEmailRequest {
+            from_email_address: email.from,
+            destination: Destination { to_addresses: email.to },
+            content: Content {
+                raw: RawMessage { data: BASE64.encode(&email.raw_data) },
+            },
+            configuration_set_name: self.configuration_set.clone(),
+        };
+        let body = serde_json::to_vec(&request)?;
+
+        let mut builder = self.client.post(self.url.clone());
+        for (name, value) in self.sign(&body, chrono::Utc::now()) {
+            builder = builder.header(name, value);
+        }
+
+        let response = builder.body(body).send().await?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let error_type = response
+                .headers()
+                .get("x-amzn-errortype")
+                .and_then(|v| v.to_str().ok())
+                .map(|v| v.split(':').next().unwrap_or(v).to_string())
+                .unwrap_or_default();
+            let text = response.text().await.unwrap_or_default();
+            let message = format!("SES request failed: {} {} - {}", status, error_type, text);
+
+            return Err(if RETRYABLE_ERRORS.contains(&error_type.as_str()) {
+                DeliveryError::transient(message)
+            } else {
+                DeliveryError::from_status(status, message)
+            }.into());
+        }
+
+        let ses_response: serde_json::Value = response.json().await.unwrap_or_default();
+        tracing::info!(
+            "SES email sent successfully. ID: {}",
+            ses_response
+                .get("MessageId")
+                .and_then(|v| v.as_str())
+                .unwrap_or("unknown")
+        );
+
+        Ok(())
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// AWS Signature Version 4 headers for a POST of `body` to `self.url`
+    /// https://docs.aws.amazon.com/IAM/latest/UserGuide/reference_sigv-create-signed-request.html
+    fn sign(&self, body: &[u8], now: chrono::DateTime<chrono::Utc>) -> Vec<(&'static str, String)> {
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let date = now.format("%Y%m%d").to_string();
+
+        // Must match the Host header reqwest sends, which includes non-default ports
+        let host = match self.url.port() {
+            Some(port) => format!("{}:{}", self.url.host_str().unwrap_or_default(), port),
+            None => self.url.host_str().unwrap_or_default().to_string(),
+        };
+
+        // Canonical headers have to be sorted by name
+        let mut headers = vec![
+            ("content-type", "application/json".to_string()),
+            ("host", host),
+            ("x-amz-date", amz_date.clone()),
+        ];
+        if let Some(token) = &self.credentials.session_token {
+            headers.push(("x-amz-security-token", token.clone()));
+        }
+
+        let canonical_headers: String = headers
+            .iter()
+            .map(|(name, value)| format!("{}:{}\n", name, value.trim()))
+            .collect();
+        let signed_headers = headers
+            .iter()
+            .map(|(name, _)| *name)
+            .collect::<Vec<_>>()
+            .join(";");
+
+        let canonical_request = format!(
+            "POST\n{}\n\n{}\n{}\n{}",
+            self.url.path(),
+            canonical_headers,
+            signed_headers,
+            hex::encode(Sha256::digest(body)),
+        );
+
+        let scope = format!("{}/{}/ses/aws4_request", date, self.region);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            amz_date,
+            scope,
+            hex::encode(Sha256::digest(canonical_request.as_bytes())),
+        );
+
+        let key = format!("AWS4{}", self.credentials.secret_access_key);
+        let key = hmac_sha256(key.as_bytes(), date.as_bytes());
+        let key = hmac_sha256(&key, self.region.as_bytes());
+        let key = hmac_sha256(&key, b"ses");
+        let key = hmac_sha256(&key, b"aws4_request");
+        let signature = hex::encode(hmac_sha256(&key, string_to_sign.as_bytes()));
+
+        let authorization = format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            self.credentials.access_key_id, scope, signed_headers, signature,
+        );
+
+        // reqwest adds the Host header itself
+        let mut signed: Vec<(&'static str, String)> = headers
+            .into_iter()
+            .filter(|(name, _)| *name != "host")
+            .collect();
+        signed.push(("authorization", authorization));
+        signed
+    }
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::strategies::{failure_kind, FailureKind};
+    use chrono::TimeZone;
+    use serde_json::json;
+    use wiremock::matchers::{body_json, header, header_exists, method, path};
+    use wiremock::{Mock, MockServer, Request, ResponseTemplate};
+
+    fn strategy(api_url: &str) -> SesStrategy {
+        let config = serde_json::from_value(json!({
+            "region": "us-east-1",
+            "access_key_id": "AKIDEXAMPLE",
+            "secret_access_key": "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY",
+            "session_token": "session",
+            "configuration_set": "relay",
+            "api_url": api_url,
+        }))
+        .unwrap();
+        SesStrategy::new("ses".to_string(), config).unwrap()
+    }
+
+    #[test]
+    fn sign_produces_the_sigv4_authorization_header() {
+        let strategy = strategy("https://email.us-east-1.amazonaws.com");
+        let now = chrono::Utc.with_ymd_and_hms(2015, 8, 30, 12, 36, 0).unwrap();
+
+        let headers = strategy.sign(br#"{"FromEmailAddress":"sender@example.com"}"#, now);
+
+        assert_eq!(
+            headers,
+            [
+                ("content-type", "application/json".to_string()),
+                ("x-amz-date", "20150830T123600Z".to_string()),
+                ("x-amz-security-token", "session".to_string()),
+                (
+                    "authorization",
+                    "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/ses/aws4_request, \
+                     SignedHeaders=content-type;host;x-amz-date;x-amz-security-token, \
+                     Signature=f4d9b50a8dd149c391d5041fb0c745aa69063a5dc6c4f1370f635662299cc38c"
+                        .to_string()
+                ),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn sends_the_raw_message_signed_for_the_host_it_connects_to() {
+        let server = MockServer::start().await;
+        let host = server.address().to_string();
+        Mock::given(method("POST"))
+            .and(path("/v2/email/outbound-emails"))
+            .and(header("host", host.as_str()))
+            .and(header_exists("x-amz-date"))
+            .and(header("x-amz-security-token", "session"))
+            .and(|request: &Request| {
+                request.headers.get("authorization").and_then(|value| value.to_str().ok()).is_some_and(|value| {
+                    value.starts_with("AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/")
+                        && value.contains("/us-east-1/ses/aws4_request, SignedHeaders=content-type;host;x-amz-date;x-amz-security-token, ")
+                })
+            })
+            .and(body_json(json!({
+                "FromEmailAddress": "sender@example.com",
+                "Destination": { "ToAddresses": ["a@example.com"] },
+                "Content": { "Raw": { "Data": BASE64.encode("Subject: Test\r\n\r\nHello\r\n") } },
+                "ConfigurationSetName": "relay",
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "MessageId": "abc" })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let email = EmailData::for_test(&["a@example.com"], "Subject: Test\r\n\r\nHello\r\n");
+        strategy(&server.uri()).send_email(email).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn errors_are_classified_by_error_type_and_status() {
+        let server = MockServer::start().await;
+        let cases = [
+            (400, "MessageRejected", FailureKind::Permanent),
+            (400, "SendingPausedException:http://internal.amazon.com/coral/", FailureKind::Transient),
+            (429, "TooManyRequestsException", FailureKind::Transient),
+            (500, "InternalFailure", FailureKind::Transient),
+        ];
+
+        for (status, error_type, kind) in cases {
+            server.reset().await;
+            Mock::given(method("POST"))
+                .respond_with(ResponseTemplate::new(status).insert_header("x-amzn-errortype", error_type))
+                .mount(&server)
+                .await;
+
+            let email = EmailData::for_test(&["a@example.com"], "Subject: Test\r\n\r\nHello\r\n");
+            let err = strategy(&server.uri()).send_email(email).await.unwrap_err();
+            assert_eq!(failure_kind(&err), kind, "{} {}", status, error_type);
+        }
+    }
+}
@@ -0,0 +1,241 @@
+use serde_json::{json, Value};
+
+use super::mime::{decode_header_value, parse_email, truncate};
+use super::{DeliveryError, EmailData};
+use crate::config::SlackConfig;
+
+/// Slack incoming webhook strategy posting a Block Kit summary of each message
+/// https://api.slack.com/messaging/webhooks
+///
+/// A route's `channel` and `username` take precedence over the configured ones.
+#[derive(Debug, Clone)]
+pub struct SlackStrategy {
+    id: String,
+    client: reqwest::Client,
+    webhook_url: String,
+    channel: Option<String>,
+    username: Option<String>,
+}
+
+/// Block Kit limits, in characters
+/// https://api.slack.com/reference/block-kit/blocks
+const MAX_HEADER_LEN: usize = 150;
+const MAX_FIELD_LEN: usize = 2000;
+const MAX_SECTION_LEN: usize = 3000;
+
+const TRUNCATED: &str = "… (truncated)";
+
+#[derive(serde::Serialize)]
+struct SlackMessage {
+    /// Fallback for notifications and clients that cannot show blocks
+    text: String,
+    blocks: Vec<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    channel: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    username: Option<String>,
+}
+
+impl SlackStrategy {
+    pub fn new(id: String, config: SlackConfig) -> anyhow::Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(std::time::Duration::from_secs(30))
+            .build()?;
+
+        Ok(Self {
+            id,
+            client,
+            webhook_url: config.webhook_url,
+            channel: config.channel,
+            username: config.username,
+        })
+    }
+
+    pub async fn send_email(&self, email: EmailData) -> anyhow::Result<()> {
+        let message = self.build_message(&email);
+
+        let response = self.client
+            .post(&self.webhook_url)
+            .json(&message)
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let text = response.text().await.unwrap_or_default();
+            return Err(DeliveryError::from_status(status, format!("Slack webhook request failed: {} - {}", status, text)).into());
+        }
+
+        tracing::info!("Slack strategy {} posted message from {}", self.id, email.from);
+        Ok(())
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn build_message(&self, email: &EmailData) -> SlackMessage {
+        let parsed = parse_email(&email.raw_text());
+        let subject = decode_header_value(email.subject.trim());
+        let subject = if subject.is_empty() { "(no subject)" } else { subject.as_str() };
+
+        let mut blocks = vec![
+            json!({
+                "type": "header",
+                "text": { "type": "plain_text", "text": truncate(subject, MAX_HEADER_LEN, "…") },
+            }),
+            json!({
+                "type": "section",
+                "fields": [
+                    mrkdwn(&truncate(&format!("*From:*\n{}", escape(&email.from)), MAX_FIELD_LEN, TRUNCATED)),
+                    mrkdwn(&truncate(&format!("*To:*\n{}", escape(&email.to.join(", "))), MAX_FIELD_LEN, TRUNCATED)),
+                ],
+            }),
+        ];
+
+        let body = parsed.plain_text();
+        if !body.is_empty() {
+            blocks.push(json!({
+                "type": "section",
+                "text": { "type": "plain_text", "text": truncate(&body, MAX_SECTION_LEN, TRUNCATED), "emoji": false },
+            }));
+        }
+
+        if !parsed.attachments.is_empty() {
+            let names: Vec<String> = parsed
+                .attachments
+                .iter()
+                .map(|attachment| format!("{} ({})", attachment.filename, attachment.display_size()))
+                .collect();
+            blocks.push(json!({
+                "type": "context",
+                "elements": [{
+                    "type": "plain_text",
+                    "text": truncate(&format!("📎 {}", names.join(", ")), MAX_FIELD_LEN, TRUNCATED),
+                    "emoji": true,
+                }],
+            }));
+        }
+
+        SlackMessage {
+            text: escape(&format!("{} (from {})", subject, email.from)),
+            blocks,
+            channel: email.overrides.channel.clone().or_else(|| self.channel.clone()),
+            username: email.overrides.username.clone().or_else(|| self.username.clone()),
+        }
+    }
+}
+
+fn mrkdwn(text: &str) -> Value {
+    json!({ "type": "mrkdwn", "text": text })
+}
+
+/// Escape the characters Slack treats as control sequences in mrkdwn
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::RouteOverrides;
+    use crate::strategies::{failure_kind, FailureKind};
+    use wiremock::matchers::{body_json, body_partial_json, method};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn strategy(server: &MockServer) -> SlackStrategy {
+        let config = serde_json::from_value(json!({
+            "webhook_url": server.uri(),
+            "channel": "#general",
+            "username": "relay",
+        }))
+        .unwrap();
+        SlackStrategy::new("slack".to_string(), config).unwrap()
+    }
+
+    #[tokio::test]
+    async fn posts_a_block_summary_with_the_configured_channel() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(body_json(json!({
+                "text": "Disk full (from cron@example.com)",
+                "blocks": [
+                    { "type": "header", "text": { "type": "plain_text", "text": "Disk full" } },
+                    {
+                        "type": "section",
+                        "fields": [
+                            { "type": "mrkdwn", "text": "*From:*\ncron@example.com" },
+                            { "type": "mrkdwn", "text": "*To:*\n&lt;ops&gt;@example.com" },
+                        ],
+                    },
+                    { "type": "section", "text": { "type": "plain_text", "text": "/ is 99% full", "emoji": false } },
+                ],
+                "channel": "#general",
+                "username": "relay",
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_string("ok"))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let email = EmailData {
+            from: "cron@example.com".to_string(),
+            subject: "Disk full".to_string(),
+            ..EmailData::for_test(&["<ops>@example.com"], "Subject: Disk full\r\n\r\n/ is 99% full\r\n")
+        };
+        strategy(&server).send_email(email).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn route_overrides_replace_channel_and_username() {
+        let server = MockServer::start().await;
+        Mock::given(body_partial_json(json!({ "channel": "#cron", "username": "cron" })))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(body_partial_json(json!({ "channel": "#alerts", "username": "relay" })))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let strategy = strategy(&server);
+        let mut email = EmailData::for_test(&["a@example.com"], "Subject: Test\r\n\r\nhi\r\n");
+        email.overrides = RouteOverrides { channel: Some("#cron".to_string()), username: Some("cron".to_string()) };
+        strategy.send_email(email.clone()).await.unwrap();
+
+        email.overrides = RouteOverrides { channel: Some("#alerts".to_string()), username: None };
+        strategy.send_email(email).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn long_bodies_are_cut_at_the_section_limit() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST")).respond_with(ResponseTemplate::new(200)).expect(1).mount(&server).await;
+
+        let raw = format!("Subject: Test\r\n\r\n{}\r\n", "é".repeat(5000));
+        strategy(&server).send_email(EmailData::for_test(&["a@example.com"], &raw)).await.unwrap();
+
+        let requests = server.received_requests().await.unwrap();
+        let payload: Value = requests[0].body_json().unwrap();
+        let text = payload["blocks"][2]["text"]["text"].as_str().unwrap();
+        assert_eq!(text.chars().count(), MAX_SECTION_LEN);
+        assert!(text.ends_with("éé… (truncated)"));
+    }
+
+    #[tokio::test]
+    async fn errors_are_classified_by_status() {
+        let server = MockServer::start().await;
+        for (status, kind) in [(404, FailureKind::Permanent), (429, FailureKind::Transient), (500, FailureKind::Transient)] {
+            server.reset().await;
+            Mock::given(method("POST"))
+                .respond_with(ResponseTemplate::new(status).set_body_string("channel_not_found"))
+                .mount(&server)
+                .await;
+            let email = EmailData::for_test(&["a@example.com"], "Subject: Test\r\n\r\nhi\r\n");
+            let error = strategy(&server).send_email(email).await.unwrap_err();
+            assert_eq!(failure_kind(&error), kind, "status {}", status);
+        }
+    }
+}
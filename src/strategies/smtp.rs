@@ -0,0 +1,526 @@
+use std::future::Future;
+use std::time::Duration;
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufStream};
+use tokio::net::TcpStream;
+use tokio_native_tls::{native_tls, TlsConnector};
+
+use super::{DeliveryError, EmailData};
+use crate::config::{SmtpConfig, UpstreamTls};
+use crate::smtp::SmtpStream;
+
+type Connection = BufStream<Box<dyn SmtpStream>>;
+
+/// Relays the raw message through an upstream SMTP server (smarthost)
+///
+/// A new connection is opened for every message. Upstream 4xx replies and
+/// network errors are transient, 5xx replies are permanent.
+#[derive(Debug, Clone)]
+pub struct SmtpStrategy {
+    id: String,
+    host: String,
+    port: u16,
+    tls: UpstreamTls,
+    credentials: Option<(String, String)>,
+    helo_name: String,
+    accept_invalid_certs: bool,
+    timeout: Duration,
+}
+
+/// A complete, possibly multi-line, server reply
+struct Reply {
+    code: u16,
+    lines: Vec<String>,
+}
+
+impl Reply {
+    fn text(&self) -> String {
+        format!("{} {}", self.code, self.lines.join(" "))
+    }
+}
+
+impl SmtpStrategy {
+    pub fn new(id: String, config: SmtpConfig) -> anyhow::Result<Self> {
+        let credentials = match (config.username, config.password) {
+            (Some(username), Some(password)) => Some((username, password)),
+            (None, None) => None,
+            _ => anyhow::bail!("SMTP strategy {} needs both username and password for AUTH", id),
+        };
+
+        Ok(Self {
+            id,
+            host: config.host,
+            port: config.port,
+            tls: config.tls,
+            credentials,
+            helo_name: config.helo_name,
+            accept_invalid_certs: config.accept_invalid_certs,
+            timeout: Duration::from_secs(config.timeout_secs),
+        })
+    }
+
+    pub async fn send_email(&self, email: EmailData) -> anyhow::Result<()> {
+        let (mut stream, capabilities) = self.open().await?;
+        let result = self.transaction(&mut stream, &capabilities, &email).await;
+
+        // The outcome is already decided, a failing QUIT does not change it
+        let _ = self.command(&mut stream, "QUIT").await;
+        result?;
+
+        tracing::info!("SMTP {} relayed message via {}:{}", self.id, self.host, self.port);
+        Ok(())
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Connect, negotiate TLS and authenticate; returns the EHLO capabilities
+    async fn open(&self) -> anyhow::Result<(Connection, Vec<String>)> {
+        let tcp = self.timed("connecting", TcpStream::connect((self.host.as_str(), self.port))).await?
+            .map_err(|err| DeliveryError::transient(format!("Failed to connect to {}:{}: {}", self.host, self.port, err)))?;
+
+        let mut stream: Connection = match self.tls {
+            UpstreamTls::Implicit => BufStream::new(Box::new(self.handshake(tcp).await?)),
+            UpstreamTls::None | UpstreamTls::Starttls => BufStream::new(Box::new(tcp)),
+        };
+
+        let greeting = self.read_reply(&mut stream).await?;
+        self.expect(&greeting, 2, "connection")?;
+
+        let mut capabilities = self.ehlo(&mut stream).await?;
+
+        if self.tls == UpstreamTls::Starttls {
+            if !has_capability(&capabilities, "STARTTLS") {
+                return Err(DeliveryError::permanent(format!("{} does not offer STARTTLS", self.host)).into());
+            }
+            let reply = self.command(&mut stream, "STARTTLS").await?;
+            self.expect(&reply, 2, "STARTTLS")?;
+
+            stream = BufStream::new(Box::new(self.handshake(stream.into_inner()).await?));
+            // Capabilities may differ once the connection is encrypted (RFC 3207 section 4.2)
+            capabilities = self.ehlo(&mut stream).await?;
+        }
+
+        if let Some((username, password)) = &self.credentials {
+            self.authenticate(&mut stream, &capabilities, username, password).await?;
+        }
+
+        Ok((stream, capabilities))
+    }
+
+    async fn handshake<S>(&self, stream: S) -> anyhow::Result<tokio_native_tls::TlsStream<S>>
+    where
+        S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+    {
+        let connector = native_tls::TlsConnector::builder()
+            .danger_accept_invalid_certs(self.accept_invalid_certs)
+            .build()?;
+
+        self.timed("the TLS handshake", TlsConnector::from(connector).connect(&self.host, stream)).await?
+            .map_err(|err| DeliveryError::transient(format!("TLS handshake with {} failed: {}", self.host, err)).into())
+    }
+
+    async fn ehlo(&self, stream: &mut Connection) -> anyhow::Result<Vec<String>> {
+        let reply = self.command(stream, &format!("EHLO {}", self.helo_name)).await?;
+        self.expect(&reply, 2, "EHLO")?;
+
+        // The first line is the server greeting, the rest are extensions
+        Ok(reply.lines.into_iter().skip(1).collect())
+    }
+
+    async fn authenticate(
+        &self,
+        stream: &mut Connection,
+        capabilities: &[String],
+        username: &str,
+        password: &str,
+    ) -> anyhow::Result<()> {
+        let mechanisms: Vec<String> = capabilities
+            .iter()
+            .filter_map(|line| {
+                let mut words = line.split_whitespace();
+                words.next()
+                    .filter(|keyword| keyword.eq_ignore_ascii_case("AUTH"))
+                    .map(|_| words.map(str::to_uppercase).collect::<Vec<_>>())
+            })
+            .flatten()
+            .collect();
+
+        if mechanisms.iter().any(|m| m == "PLAIN") {
+            let token = BASE64.encode(format!("\0{}\0{}", username, password));
+            let reply = self.command(stream, &format!("AUTH PLAIN {}", token)).await?;
+            self.expect(&reply, 2, "AUTH")
+        } else if mechanisms.iter().any(|m| m == "LOGIN") {
+            let reply = self.command(stream, "AUTH LOGIN").await?;
+            self.expect(&reply, 3, "AUTH")?;
+            let reply = self.command(stream, &BASE64.encode(username)).await?;
+            self.expect(&reply, 3, "AUTH")?;
+            let reply = self.command(stream, &BASE64.encode(password)).await?;
+            self.expect(&reply, 2, "AUTH")
+        } else {
+            Err(DeliveryError::permanent(format!(
+                "{} does not offer AUTH PLAIN or LOGIN on this connection",
+                self.host
+            )).into())
+        }
+    }
+
+    /// Run the mail transaction on an open connection
+    async fn transaction(
+        &self,
+        stream: &mut Connection,
+        capabilities: &[String],
+        email: &EmailData,
+    ) -> anyhow::Result<()> {
+        let data = encode_data(&email.raw_data);
+
+        let mut mail_from = format!("MAIL FROM:<{}>", email.from);
+        if has_capability(capabilities, "SIZE") {
+            mail_from.push_str(&format!(" SIZE={}", email.raw_data.len()));
+        }
+        if has_capability(capabilities, "8BITMIME") && !email.raw_data.is_ascii() {
+            mail_from.push_str(" BODY=8BITMIME");
+        }
+        let reply = self.command(stream, &mail_from).await?;
+        self.expect(&reply, 2, "MAIL FROM")?;
+
+        // A partial delivery would be retried as a whole, so any refused recipient fails the message
+        let mut rejected = Vec::new();
+        let mut transient = false;
+        for recipient in &email.to {
+            let reply = self.command(stream, &format!("RCPT TO:<{}>", recipient)).await?;
+            if reply.code / 100 != 2 {
+                transient |= reply.code < 500;
+                rejected.push(format!("{} ({})", recipient, reply.text()));
+            }
+        }
+        if !rejected.is_empty() {
+            let _ = self.command(stream, "RSET").await;
+            let message = format!("{} refused recipients: {}", self.host, rejected.join(", "));
+            return Err(if transient {
+                DeliveryError::transient(message)
+            } else {
+                DeliveryError::permanent(message)
+            }.into());
+        }
+
+        let reply = self.command(stream, "DATA").await?;
+        self.expect(&reply, 3, "DATA")?;
+
+        self.timed("sending the message", async {
+            stream.write_all(&data).await?;
+            stream.flush().await
+        }).await??;
+
+        let reply = self.read_reply(stream).await?;
+        self.expect(&reply, 2, "the message")
+    }
+
+    async fn command(&self, stream: &mut Connection, command: &str) -> anyhow::Result<Reply> {
+        self.timed("sending a command", async {
+            stream.write_all(command.as_bytes()).await?;
+            stream.write_all(b"\r\n").await?;
+            stream.flush().await
+        }).await??;
+
+        self.read_reply(stream).await
+    }
+
+    async fn read_reply(&self, stream: &mut Connection) -> anyhow::Result<Reply> {
+        let mut lines = Vec::new();
+        let mut line = String::new();
+
+        loop {
+            line.clear();
+            let bytes_read = self.timed("a reply", stream.read_line(&mut line)).await??;
+            if bytes_read == 0 {
+                return Err(DeliveryError::transient(format!("{} closed the connection", self.host)).into());
+            }
+
+            let trimmed = line.trim_end();
+            let code = trimmed.get(..3).and_then(|code| code.parse::<u16>().ok());
+            let Some(code) = code else {
+                return Err(DeliveryError::transient(format!("Malformed reply from {}: {}", self.host, trimmed)).into());
+            };
+            lines.push(trimmed.get(4..).unwrap_or_default().to_string());
+
+            // "250-" continues a multi-line reply, "250 " ends it
+            if trimmed.as_bytes().get(3) != Some(&b'-') {
+                return Ok(Reply { code, lines });
+            }
+        }
+    }
+
+    /// Fail unless the reply is in the expected class (2 for 2xx, 3 for 3xx)
+    fn expect(&self, reply: &Reply, class: u16, stage: &str) -> anyhow::Result<()> {
+        if reply.code / 100 == class {
+            return Ok(());
+        }
+
+        let message = format!("{} rejected {}: {}", self.host, stage, reply.text());
+        Err(if reply.code >= 500 {
+            DeliveryError::permanent(message)
+        } else {
+            DeliveryError::transient(message)
+        }.into())
+    }
+
+    async fn timed<F: Future>(&self, what: &str, future: F) -> anyhow::Result<F::Output> {
+        tokio::time::timeout(self.timeout, future).await.map_err(|_| {
+            DeliveryError::transient(format!("Timed out waiting for {} on {}", what, self.host)).into()
+        })
+    }
+}
+
+fn has_capability(capabilities: &[String], name: &str) -> bool {
+    capabilities.iter().any(|line| {
+        line.split_whitespace()
+            .next()
+            .is_some_and(|keyword| keyword.eq_ignore_ascii_case(name))
+    })
+}
+
+/// Normalize line endings to CRLF, dot-stuff and terminate the message for DATA
+fn encode_data(raw: &[u8]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(raw.len() + 5);
+
+    for line in raw.split_inclusive(|&b| b == b'\n') {
+        let line = line.strip_suffix(b"\n").unwrap_or(line);
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        if line.starts_with(b".") {
+            data.push(b'.');
+        }
+        data.extend_from_slice(line);
+        data.extend_from_slice(b"\r\n");
+    }
+
+    data.extend_from_slice(b".\r\n");
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::strategies::{failure_kind, FailureKind};
+    use tokio::net::TcpListener;
+    use tokio_native_tls::TlsAcceptor;
+
+    /// How the fake upstream server behaves
+    #[derive(Clone, Copy)]
+    struct Script {
+        tls: UpstreamTls,
+        auth: &'static str,
+        rcpt_reply: &'static str,
+        data_reply: &'static str,
+    }
+
+    impl Default for Script {
+        fn default() -> Self {
+            Self { tls: UpstreamTls::None, auth: "PLAIN LOGIN", rcpt_reply: "250 OK", data_reply: "250 Queued" }
+        }
+    }
+
+    fn acceptor() -> TlsAcceptor {
+        let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let identity = native_tls::Identity::from_pkcs8(
+            certified.cert.pem().as_bytes(),
+            certified.key_pair.serialize_pem().as_bytes(),
+        )
+        .unwrap();
+        TlsAcceptor::from(native_tls::TlsAcceptor::new(identity).unwrap())
+    }
+
+    /// Start a fake upstream server for one connection; resolves to the lines the client sent
+    async fn fake_server(script: Script) -> (u16, tokio::task::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let server = tokio::spawn(async move {
+            let acceptor = acceptor();
+            let (tcp, _) = listener.accept().await.unwrap();
+            let mut stream: Connection = match script.tls {
+                UpstreamTls::Implicit => BufStream::new(Box::new(acceptor.accept(tcp).await.unwrap())),
+                _ => BufStream::new(Box::new(tcp)),
+            };
+            let mut encrypted = script.tls == UpstreamTls::Implicit;
+            let mut transcript = Vec::new();
+            let mut in_data = false;
+
+            stream.write_all(b"220 fake ESMTP\r\n").await.unwrap();
+            stream.flush().await.unwrap();
+
+            loop {
+                let mut line = String::new();
+                if stream.read_line(&mut line).await.unwrap() == 0 {
+                    break;
+                }
+                transcript.push(line.trim_end_matches("\r\n").to_string());
+
+                let reply = if in_data {
+                    if line != ".\r\n" {
+                        continue;
+                    }
+                    in_data = false;
+                    script.data_reply.to_string()
+                } else {
+                    let command = line.to_ascii_uppercase();
+                    if command.starts_with("EHLO") {
+                        let starttls = if script.tls == UpstreamTls::Starttls && !encrypted { "250-STARTTLS\r\n" } else { "" };
+                        format!("250-fake\r\n250-SIZE 1000000\r\n{}250 AUTH {}", starttls, script.auth)
+                    } else if command.starts_with("STARTTLS") {
+                        stream.write_all(b"220 Go ahead\r\n").await.unwrap();
+                        stream.flush().await.unwrap();
+                        stream = BufStream::new(Box::new(acceptor.accept(stream.into_inner()).await.unwrap()));
+                        encrypted = true;
+                        continue;
+                    } else if command.starts_with("AUTH PLAIN") {
+                        "235 Authenticated".to_string()
+                    } else if command.starts_with("AUTH LOGIN") || transcript.iter().rev().nth(1).is_some_and(|l| l.starts_with("AUTH LOGIN")) {
+                        "334 VXNlcm5hbWU6".to_string()
+                    } else if transcript.iter().rev().nth(2).is_some_and(|l| l.starts_with("AUTH LOGIN")) {
+                        "235 Authenticated".to_string()
+                    } else if command.starts_with("RCPT") {
+                        script.rcpt_reply.to_string()
+                    } else if command.starts_with("DATA") {
+                        in_data = true;
+                        "354 End data with <CR><LF>.<CR><LF>".to_string()
+                    } else if command.starts_with("QUIT") {
+                        stream.write_all(b"221 Bye\r\n").await.unwrap();
+                        stream.flush().await.unwrap();
+                        break;
+                    } else {
+                        "250 OK".to_string()
+                    }
+                };
+
+                stream.write_all(format!("{}\r\n", reply).as_bytes()).await.unwrap();
+                stream.flush().await.unwrap();
+            }
+
+            transcript
+        });
+
+        (port, server)
+    }
+
+    fn strategy(port: u16, tls: UpstreamTls, credentials: bool) -> SmtpStrategy {
+        let mut config = serde_json::json!({
+            "host": "127.0.0.1",
+            "port": port,
+            "tls": tls,
+            "helo_name": "relay.test",
+            "accept_invalid_certs": true,
+            "timeout_secs": 5,
+        });
+        if credentials {
+            config["username"] = "user".into();
+            config["password"] = "secret".into();
+        }
+        SmtpStrategy::new("smtp".to_string(), serde_json::from_value(config).unwrap()).unwrap()
+    }
+
+    fn email() -> EmailData {
+        EmailData::for_test(&["a@example.com"], "Subject: Test\r\n\r\n.hidden dot\r\nbody\r\n")
+    }
+
+    async fn send(script: Script, tls: UpstreamTls, credentials: bool) -> (anyhow::Result<()>, Vec<String>) {
+        let (port, server) = fake_server(script).await;
+        let result = strategy(port, tls, credentials).send_email(email()).await;
+        (result, server.await.unwrap())
+    }
+
+    #[tokio::test]
+    async fn relays_the_message_in_plaintext() {
+        let (result, transcript) = send(Script::default(), UpstreamTls::None, false).await;
+
+        result.unwrap();
+        assert_eq!(
+            transcript,
+            [
+                "EHLO relay.test",
+                "MAIL FROM:<sender@example.com> SIZE=36",
+                "RCPT TO:<a@example.com>",
+                "DATA",
+                "Subject: Test",
+                "",
+                "..hidden dot",
+                "body",
+                ".",
+                "QUIT",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn temporary_recipient_rejections_are_transient() {
+        let script = Script { rcpt_reply: "450 Mailbox busy", ..Default::default() };
+        let (result, transcript) = send(script, UpstreamTls::None, false).await;
+
+        assert_eq!(failure_kind(&result.unwrap_err()), FailureKind::Transient);
+        assert!(transcript.contains(&"RSET".to_string()));
+        assert!(!transcript.contains(&"DATA".to_string()));
+    }
+
+    #[tokio::test]
+    async fn permanent_recipient_rejections_are_permanent() {
+        let script = Script { rcpt_reply: "550 No such user", ..Default::default() };
+        let (result, _) = send(script, UpstreamTls::None, false).await;
+
+        assert_eq!(failure_kind(&result.unwrap_err()), FailureKind::Permanent);
+    }
+
+    #[tokio::test]
+    async fn message_rejections_are_classified_by_reply_code() {
+        let script = Script { data_reply: "451 Try again later", ..Default::default() };
+        let (result, _) = send(script, UpstreamTls::None, false).await;
+        assert_eq!(failure_kind(&result.unwrap_err()), FailureKind::Transient);
+
+        let script = Script { data_reply: "554 Message refused", ..Default::default() };
+        let (result, _) = send(script, UpstreamTls::None, false).await;
+        assert_eq!(failure_kind(&result.unwrap_err()), FailureKind::Permanent);
+    }
+
+    #[tokio::test]
+    async fn starttls_upgrades_before_authenticating() {
+        let script = Script { tls: UpstreamTls::Starttls, ..Default::default() };
+        let (result, transcript) = send(script, UpstreamTls::Starttls, true).await;
+
+        result.unwrap();
+        assert_eq!(
+            transcript[..4],
+            [
+                "EHLO relay.test",
+                "STARTTLS",
+                "EHLO relay.test",
+                &format!("AUTH PLAIN {}", BASE64.encode("\0user\0secret")),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn missing_starttls_is_a_permanent_failure() {
+        let (result, transcript) = send(Script::default(), UpstreamTls::Starttls, false).await;
+
+        assert_eq!(failure_kind(&result.unwrap_err()), FailureKind::Permanent);
+        assert!(!transcript.iter().any(|line| line.starts_with("MAIL")));
+    }
+
+    #[tokio::test]
+    async fn implicit_tls_with_auth_login() {
+        let script = Script { tls: UpstreamTls::Implicit, auth: "LOGIN", ..Default::default() };
+        let (result, transcript) = send(script, UpstreamTls::Implicit, true).await;
+
+        result.unwrap();
+        assert_eq!(
+            transcript[..4],
+            ["EHLO relay.test", "AUTH LOGIN", &BASE64.encode("user"), &BASE64.encode("secret")]
+        );
+    }
+
+    #[test]
+    fn encode_data_normalizes_line_endings_and_stuffs_dots() {
+        assert_eq!(encode_data(b"a\n.b\r\n..c\xe9"), b"a\r\n..b\r\n...c\xe9\r\n.\r\n");
+    }
+}
@@ -1,9 +1,11 @@
-use super::EmailData;
+use super::{DeliveryError, EmailData};
+use crate::config::WebhookConfig;
 use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
 
 /// Generic webhook strategy for sending emails to any HTTP endpoint
 #[derive(Debug, Clone)]
 pub struct WebhookStrategy {
+    id: String,
     client: reqwest::Client,
     url: String,
     headers: HeaderMap,
@@ -17,10 +19,12 @@ struct WebhookPayload {
     body: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     html: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    authenticated_user: Option<String>,
 }
 
 impl WebhookStrategy {
-    pub fn new(url: String, extra_headers: Option<Vec<(String, String)>>) -> anyhow::Result<Self> {
+    pub fn new(id: String, config: WebhookConfig) -> anyhow::Result<Self> {
         let client = reqwest::Client::builder()
             .timeout(std::time::Duration::from_secs(30))
             .build()?;
@@ -32,30 +36,31 @@ impl WebhookStrategy {
         );
         
         // Add any extra headers
-        if let Some(extra) = extra_headers {
-            for (key, value) in extra {
-                if let Ok(header_name) = HeaderName::from_bytes(key.as_bytes()) {
-                    if let Ok(header_value) = HeaderValue::from_str(&value) {
-                        headers.insert(header_name, header_value);
-                    }
-                }
-            }
+        for (key, value) in config.extra_headers {
+            let header_name = HeaderName::from_bytes(key.as_bytes())
+                .map_err(|_| anyhow::anyhow!("Invalid header name \"{}\" for webhook strategy {}", key, id))?;
+            let header_value = HeaderValue::from_str(&value)
+                .map_err(|_| anyhow::anyhow!("Invalid value for header \"{}\" in webhook strategy {}", key, id))?;
+            headers.insert(header_name, header_value);
         }
         
         Ok(Self {
+            id,
             client,
-            url,
+            url: config.api_url,
             headers,
         })
     }
     
     pub async fn send_email(&self, email: EmailData) -> anyhow::Result<()> {
+        let html = extract_html(&email.raw_text());
         let payload = WebhookPayload {
             from: email.from,
             to: email.to,
             subject: email.subject,
             body: email.body.clone(),
-            html: extract_html(&email.raw_data),
+            html,
+            authenticated_user: email.authenticated_user,
         };
         
         let response = self.client
@@ -68,16 +73,15 @@ impl WebhookStrategy {
         if !response.status().is_success() {
             let status = response.status();
             let text = response.text().await.unwrap_or_default();
-            anyhow::bail!("Webhook request failed: {} - {}", status, text);
+            return Err(DeliveryError::from_status(status, format!("Webhook request failed: {} - {}", status, text)).into());
         }
         
-        tracing::info!("Webhook request successful: {}", response.status());
+        tracing::info!("Webhook {} request successful: {}", self.id, response.status());
         Ok(())
     }
-    
-    #[allow(dead_code)]
-    pub fn name(&self) -> &'static str {
-        "webhook"
+
+    pub fn id(&self) -> &str {
+        &self.id
     }
 }
 